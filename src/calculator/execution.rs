@@ -1,9 +1,15 @@
-use super::utils::floor_abs;
+use super::utils::{
+    binomial, ceil, collatz, factorial, fibonacci, floor, floor_abs, gcd, integer_log, is_prime,
+    lcm, mod_inverse, multiplicative_order, permutations, round_half_even, tetration,
+};
 use super::Token;
 use super::Token::*;
 use num_traits::{One, Zero};
 use ramp::rational::Rational;
+use ramp::Int;
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use Object::*;
 
 #[derive(PartialEq, Clone)]
@@ -13,75 +19,204 @@ pub enum Object {
     Iterative(usize, Vec<ExecTree>, ExecTree, ExecTree),
 }
 
+impl Object {
+    // Writes the definition back as rpn-l, so that parsing it defines the object again
+    pub fn to_rpn(&self, name: &str) -> String {
+        match self {
+            Variable(value) => format!("{} ={}", Number(value.clone()), name),
+            Function(arity, tree) => format!("{} {}|{}", tree.to_rpn(), name, arity),
+            // The expressions go first, then the last one and the condition
+            Iterative(arity, exps, last, cond) => {
+                let mut parts: Vec<String> = exps.iter().map(|exp| exp.to_rpn()).collect();
+                parts.push(last.to_rpn());
+                parts.push(cond.to_rpn());
+                format!("{} {}@{}", parts.join(" "), name, arity)
+            }
+        }
+    }
+
+    // Names used by the definition
+    pub fn identifiers(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        match self {
+            Variable(_) => {}
+            Function(_, tree) => tree.identifiers(&mut names),
+            Iterative(_, exps, last, cond) => {
+                for exp in exps {
+                    exp.identifiers(&mut names);
+                }
+                last.identifiers(&mut names);
+                cond.identifiers(&mut names);
+            }
+        }
+        names
+    }
+}
+
 #[derive(PartialEq, Clone)]
 pub struct ExecTree {
     pub token: Token,
     pub arguments: Vec<ExecTree>,
 }
 
+// State shared by all the reductions of a single evaluation pass
+pub struct Context {
+    // Successive squares of the bases used by Exp
+    // powers[a][i] holds a^(2^i)
+    powers: HashMap<Rational, Vec<Rational>>,
+    // Results of the function calls already computed
+    // only used when memoize is set
+    memo: HashMap<(String, Vec<Rational>), Rational>,
+    pub memoize: bool,
+    // Number of nested reductions, and the maximum allowed before giving up
+    depth: usize,
+    pub max_depth: usize,
+    // Set when the limit is hit or the computation is interrupted,
+    // so that every pending reduction gives up at once
+    exceeded: bool,
+    // Number of nodes reduced, for profiling
+    pub steps: u64,
+    // Value of steps when the computation started, and the maximum number of steps allowed
+    // (tail calls don't nest, so they are only stopped by this)
+    start: u64,
+    pub max_steps: u64,
+    // Set while a command computes a tree one node at a time,
+    // so that the nodes share the steps of a single computation
+    held: bool,
+    // Maximum size of the results of tetration
+    pub max_bits: usize,
+}
+
+// Default maximum number of nested reductions, maxdepth can only lower it
+// (it must fit in the stack of the calculator thread, see main.rs)
+pub const MAX_DEPTH: usize = 5000;
+
+// Default maximum number of nodes reduced by a single computation
+pub const MAX_STEPS: u64 = 100_000_000;
+
+// Set from the Ctrl-C handler to cancel the running computation
+// it's only read and written as a whole flag, so relaxed ordering is enough
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Number of nodes reduced between two checks of INTERRUPTED, minus one
+// (it must be a power of two minus one, to be used as a mask)
+const INTERRUPT_MASK: u64 = 1023;
+
+// Default maximum number of bits of a tetration
+pub const MAX_BITS: usize = 1 << 24;
+
+// Maximum number of bits a left shift can add
+const MAX_SHIFT: usize = 1 << 24;
+
+impl Context {
+    #[inline]
+    pub fn new() -> Context {
+        Context {
+            powers: HashMap::new(),
+            memo: HashMap::new(),
+            memoize: false,
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            exceeded: false,
+            steps: 0,
+            start: 0,
+            max_steps: MAX_STEPS,
+            held: false,
+            max_bits: MAX_BITS,
+        }
+    }
+
+    // Drop everything computed in the previous pass
+    #[inline]
+    pub fn clear(&mut self) {
+        self.powers.clear();
+        self.memo.clear();
+    }
+
+    // Start a single computation made of many reductions from depth 0
+    // reduce won't start a new one until release is called
+    #[inline]
+    pub fn hold(&mut self) {
+        self.exceeded = INTERRUPTED.load(Ordering::Relaxed);
+        self.start = self.steps;
+        self.held = true;
+    }
+
+    #[inline]
+    pub fn release(&mut self) {
+        self.held = false;
+    }
+
+    // Give up after a loop was stopped by Ctrl-C, like reduce does
+    fn interrupt<T>(&mut self) -> Option<T> {
+        eprintln!("Computation interrupted");
+        self.exceeded = true;
+        None
+    }
+}
+
+// Fails naming the first token that doesn't find enough arguments
 #[inline]
-pub fn parse_tree(stack: Vec<Token>, table: &HashMap<String, Object>) -> ExecTree {
+pub fn parse_tree(stack: Vec<Token>, table: &HashMap<String, Object>) -> Result<ExecTree, String> {
     let mut arguments = Vec::new();
 
     // Builds the tree from the stack
     // Each token gets built into a tree node and put on an arguments stack
     // when building a node, it pops arguments from the stack an pass them to the node
     for token in stack {
-        match token {
-            Identifier(ref name) => match table.get(name) {
+        let arity = match &token {
+            Identifier(name) => match table.get(name) {
                 // N-ary expressions
-                Some(Function(arity, _)) | Some(Iterative(arity, _, _, _)) => {
-                    let len = arguments.len();
-                    let args = arguments.split_off(len - arity);
-                    arguments.push(ExecTree {
-                        token,
-                        arguments: args,
-                    });
-                }
+                Some(Function(arity, _)) | Some(Iterative(arity, _, _, _)) => *arity,
 
                 // Variables
-                _ => {
-                    arguments.push(ExecTree {
-                        token,
-                        arguments: Vec::new(),
-                    });
-                }
+                _ => 0,
             },
 
             // Unary expresions
-            Number(_) | Argument(_) => {
-                arguments.push(ExecTree {
-                    token,
-                    arguments: Vec::new(),
-                });
-            }
+            Number(_) | Argument(_) => 0,
+
+            // Unary operators
+            Period | Abs | Negate | Factorial | Prime | Sign | Floor | Ceil | Round | Numerator
+            | Denominator | Digits | DigitSum | Reciprocal | Not | Collatz | Fibonacci => 1,
 
             // Binary expressions
-            Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp => {
-                let len = arguments.len();
-                let args = arguments.split_off(len - 2);
-                arguments.push(ExecTree {
-                    token,
-                    arguments: args,
-                });
-            }
+            Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp | IeeeRemainder
+            | Gcd | Lcm | Hypotenuse | Equal | NotEqual | Less | LessEqual | Greater
+            | GreaterEqual | Binomial | Permutations | BitAnd | BitOr | BitXor | ShiftLeft
+            | ShiftRight | ModInverse | Tetration | AbsDiff | IntegerLog | And | Or => 2,
 
             // Ternary expressions
-            If | ExpMod => {
-                let len = arguments.len();
-                let args = arguments.split_off(len - 3);
-                arguments.push(ExecTree {
-                    token,
-                    arguments: args,
-                });
-            }
+            If | ExpMod | Clamp | Between => 3,
+
+            _ => return Err(format!("'{}' can't be part of an expression", token)),
+        };
 
-            _ => panic!("Corrupted stack"),
+        let len = arguments.len();
+        if len < arity {
+            return Err(format!(
+                "{} '{}' needs {} arguments but found {}",
+                if let Identifier(_) = token {
+                    "function"
+                } else {
+                    "operator"
+                },
+                token,
+                arity,
+                len
+            ));
         }
+        let args = arguments.split_off(len - arity);
+        arguments.push(ExecTree {
+            token,
+            arguments: args,
+        });
     }
 
-    // At the end, the only argument in stack will be the root node of the computation
-    arguments.pop().unwrap()
+    // At the end, the last argument in stack will be the root node of the computation
+    arguments
+        .pop()
+        .ok_or_else(|| String::from("Empty expression"))
 }
 
 // Tail recursive Fibonacci for testing
@@ -91,14 +226,45 @@ pub fn parse_tree(stack: Vec<Token>, table: &HashMap<String, Object>) -> ExecTre
 // $0 1 ~ nfib $0 2 ~ nfib + $0 $0 1 ~ ? nfib|1
 //
 // Iterative Fibonacci for testing
-// $1 $0 $1 + $2 1 ~ $1 $2 fib_aux@3 1 0 $0 fib_aux fib|1
+// $1 $0 $1 + $2 1 ~ $1 $2 fib_aux@3 1 0 $0 fib_aux ifib|1
 impl ExecTree {
     // The result needs to be optional because
     // we don't know in advance if an expression contains errors
+    // Keeps count of the nested reductions, so that a runaway recursion
+    // returns None instead of overflowing the stack
     pub fn reduce(
         &self,
         table: &HashMap<String, Object>,
         args: &Vec<Option<Rational>>,
+        context: &mut Context,
+    ) -> Option<Rational> {
+        // A new computation starts from depth 0
+        // an interruption is not forgotten, the following computations of the line give up too
+        // (silently, it was already reported)
+        if context.depth == 0 && !context.held {
+            context.exceeded = INTERRUPTED.load(Ordering::Relaxed);
+            context.start = context.steps;
+        }
+        if context.exceeded {
+            return None;
+        }
+        if context.depth >= context.max_depth {
+            eprintln!("Recursion limit exceeded");
+            context.exceeded = true;
+            return None;
+        }
+
+        context.depth += 1;
+        let result = self.reduce_nested(table, args, context);
+        context.depth -= 1;
+        result
+    }
+
+    fn reduce_nested(
+        &self,
+        table: &HashMap<String, Object>,
+        args: &Vec<Option<Rational>>,
+        context: &mut Context,
     ) -> Option<Rational> {
         // If the recursive calls to reduce() used in the If, Function, and Iterative branches were
         // optimised as tail calls, all tail calls in rpn-l would also be optimised; the compiler
@@ -128,10 +294,23 @@ impl ExecTree {
         // tail calls will just modify the argument variables and let the loop go on
         // other operations will just make some recursive calls and return the combined result
         loop {
+            // Every node counts, tail calls included
+            context.steps += 1;
+            if context.steps - context.start > context.max_steps {
+                eprintln!("Recursion limit exceeded");
+                context.exceeded = true;
+                return None;
+            }
+
+            // Give up if Ctrl-C was pressed, the loop is checked so that tail calls can be stopped too
+            if context.steps & INTERRUPT_MASK == 0 && INTERRUPTED.load(Ordering::Relaxed) {
+                return context.interrupt();
+            }
+
             match token {
                 If => {
                     // The if-else statement will not evaluate all of it's arguments
-                    let condition = arguments[2].reduce(table, args);
+                    let condition = arguments[2].reduce(table, args, context);
 
                     if let Some(condition) = condition {
                         if condition.is_zero() {
@@ -151,6 +330,25 @@ impl ExecTree {
                     }
                 }
 
+                And | Or => {
+                    // Like If, the second argument is only evaluated if it's needed
+                    let first = arguments[0].reduce(table, args, context);
+
+                    return if let Some(first) = first {
+                        // 0 and x is 0, 1 or x is 1
+                        if first.is_zero() == (*token == And) {
+                            Some(Rational::from(if *token == Or { 1 } else { 0 }))
+                        } else {
+                            arguments[1]
+                                .reduce(table, args, context)
+                                .map(|second| Rational::from(if second.is_zero() { 0 } else { 1 }))
+                        }
+                    } else {
+                        // Stops in case of errors
+                        None
+                    };
+                }
+
                 Number(value) => {
                     return Some(value.clone());
                 }
@@ -170,7 +368,7 @@ impl ExecTree {
                                 // Start by executing every argument
                                 func_args = arguments
                                     .into_iter()
-                                    .map(|arg| arg.reduce(table, args))
+                                    .map(|arg| arg.reduce(table, args, context))
                                     .collect();
 
                                 // Check if some arguments didn't compute
@@ -178,6 +376,20 @@ impl ExecTree {
                                     return None;
                                 }
 
+                                // A memoized call can't be a tail call, the result must be stored
+                                if context.memoize {
+                                    let key = (
+                                        name.clone(),
+                                        func_args.iter().map(|arg| arg.clone().unwrap()).collect(),
+                                    );
+                                    if let Some(value) = context.memo.get(&key) {
+                                        return Some(value.clone());
+                                    }
+                                    let value = run_function(ops, &func_args, table, context)?;
+                                    context.memo.insert(key, value.clone());
+                                    return Some(value);
+                                }
+
                                 // This would be a tail call
                                 token = &ops.token;
                                 arguments = &ops.arguments;
@@ -194,20 +406,22 @@ impl ExecTree {
                                 // Start by executing every argument
                                 func_args = arguments
                                     .into_iter()
-                                    .map(|arg| arg.reduce(table, args))
+                                    .map(|arg| arg.reduce(table, args, context))
                                     .collect();
 
                                 // Iter untill cond returns a 0 (stop == true)
                                 // Don't iter if cond returns None
                                 while let (Some(value), false) =
-                                    (run_function(cond, &func_args, table), stop)
+                                    (run_function(cond, &func_args, table, context), stop)
                                 {
                                     // Check for 0 (the loop stops at 0)
                                     if !value.is_zero() {
                                         // Calculate new arguments from previous
                                         func_args = exps
                                             .iter()
-                                            .map(|exp| run_function(&exp, &func_args, table))
+                                            .map(|exp| {
+                                                run_function(&exp, &func_args, table, context)
+                                            })
                                             .collect();
                                     } else {
                                         // Set flag if 0
@@ -241,11 +455,36 @@ impl ExecTree {
                     };
                 }
 
+                Clamp | Between => {
+                    // Evaluates arguments
+                    let value = arguments[0].reduce(table, args, context);
+                    let lo = arguments[1].reduce(table, args, context);
+                    let hi = arguments[2].reduce(table, args, context);
+
+                    return if let (Some(value), Some(lo), Some(hi)) = (value, lo, hi) {
+                        if let Between = token {
+                            // Both ends are included
+                            Some(Rational::from(if lo <= value && value <= hi {
+                                1
+                            } else {
+                                0
+                            }))
+                        } else {
+                            // min(max(value, lo), hi), so hi wins if lo > hi
+                            let value = if value < lo { lo } else { value };
+                            Some(if value > hi { hi } else { value })
+                        }
+                    } else {
+                        // Return None if an argument didn't compute
+                        None
+                    };
+                }
+
                 ExpMod => {
                     // Evaluates arguments
-                    let a = arguments[0].reduce(table, args);
-                    let b = arguments[1].reduce(table, args);
-                    let c = arguments[2].reduce(table, args);
+                    let a = arguments[0].reduce(table, args, context);
+                    let b = arguments[1].reduce(table, args, context);
+                    let c = arguments[2].reduce(table, args, context);
 
                     return if let (Some(a), Some(b), Some(c)) = (a, b, c) {
                         // Flooring and converting to Int
@@ -267,11 +506,125 @@ impl ExecTree {
                     };
                 }
 
+                // Unary operations
+                Period | Abs | Negate | Factorial | Prime | Sign | Floor | Ceil | Round
+                | Numerator | Denominator | Digits | DigitSum | Reciprocal | Not | Collatz
+                | Fibonacci => {
+                    // Evaluates argument
+                    let a = arguments[0].reduce(table, args, context);
+
+                    return if let Some(a) = a {
+                        match token {
+                            Period => {
+                                // Factors 2 and 5 of the denominator only delay the period
+                                let (_, mut den) = a.into_parts();
+                                for factor in [2, 5].iter() {
+                                    let factor = Int::from(*factor);
+                                    while (&den % &factor).is_zero() {
+                                        den /= &factor;
+                                    }
+                                }
+                                // The period is the order of 10 in modulo den
+                                // (the order in modulo 1 is 0), every try counts as a step
+                                let max_steps = context.start.saturating_add(context.max_steps);
+                                match multiplicative_order(
+                                    &Int::from(10),
+                                    &den,
+                                    &mut context.steps,
+                                    max_steps,
+                                ) {
+                                    Some(order) => Some(Rational::from(order)),
+                                    None if context.steps > max_steps => {
+                                        eprintln!("Recursion limit exceeded");
+                                        context.exceeded = true;
+                                        None
+                                    }
+                                    None => context.interrupt(),
+                                }
+                            }
+                            Abs => Some(a.abs()),
+                            Floor => Some(Rational::from(floor(a))),
+                            Ceil => Some(Rational::from(ceil(a))),
+                            Round => Some(Rational::from(round_half_even(a))),
+                            // into_parts normalizes, the sign stays in the numerator
+                            Numerator => Some(Rational::from(a.into_parts().0)),
+                            Denominator => Some(Rational::from(a.into_parts().1)),
+                            // 0 is false, like in If
+                            Not => Some(Rational::from(if a.is_zero() { 1 } else { 0 })),
+                            Collatz => {
+                                let n = floor(a);
+                                if n < 1 {
+                                    eprintln!("Operand was not positive in collatz");
+                                    None
+                                } else {
+                                    collatz(n)
+                                        .map(Rational::from)
+                                        .or_else(|| context.interrupt())
+                                }
+                            }
+                            // The sign stays on the numerator
+                            Reciprocal => {
+                                let (num, den) = a.into_parts();
+                                if num.is_zero() {
+                                    eprintln!("Cannot reciprocate zero");
+                                    None
+                                } else if num < 0 {
+                                    Some(Rational::new(-den, -num))
+                                } else {
+                                    Some(Rational::new(den, num))
+                                }
+                            }
+                            // Both work on the decimal representation of the integer
+                            Digits => Some(Rational::from(
+                                floor_abs(a, "Operand", "digits").to_string().len(),
+                            )),
+                            DigitSum => Some(Rational::from(
+                                floor_abs(a, "Operand", "digitsum")
+                                    .to_string()
+                                    .bytes()
+                                    .map(|digit| usize::from(digit - b'0'))
+                                    .sum::<usize>(),
+                            )),
+                            Sign => Some(Rational::from(if a > Rational::zero() {
+                                1
+                            } else if a < Rational::zero() {
+                                -1
+                            } else {
+                                0
+                            })),
+                            Negate => Some(-a),
+                            Factorial => factorial(&floor_abs(a, "Operand", "factorial"))
+                                .map(Rational::from)
+                                .or_else(|| context.interrupt()),
+                            Fibonacci => {
+                                // Like the std_lib function it replaced, 0 fib is 1
+                                let n = floor_abs(a, "Index", "fib") + 1;
+                                let result = fibonacci(&n, context.max_bits);
+                                if result.is_none() {
+                                    eprintln!("Result too large, max is {} bits", context.max_bits);
+                                }
+                                result.map(Rational::from)
+                            }
+                            Prime => is_prime(&floor_abs(a, "Operand", "primality test"))
+                                .map(|prime| Rational::from(prime as u8))
+                                .or_else(|| context.interrupt()),
+
+                            // All the other tokens will never enter the tree
+                            token => {
+                                eprintln!("Corrupted stack, '{}' can't be computed", token);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                }
+
                 // Arithmetic operations, all binary operations
                 _ => {
                     // Evaluates arguments
-                    let a = arguments[0].reduce(table, args);
-                    let b = arguments[1].reduce(table, args);
+                    let a = arguments[0].reduce(table, args, context);
+                    let b = arguments[1].reduce(table, args, context);
 
                     // Execute only if both arguments computed
                     // One 'Some' is for the pop operation (it will never be None)
@@ -296,10 +649,28 @@ impl ExecTree {
                                     Some(Rational::zero())
                                 }
                             }
+                            AbsDiff => Some((a - b).abs()),
+                            IntegerLog => {
+                                let a = floor(a);
+                                let b = floor(b);
+                                if a <= 0 {
+                                    eprintln!(
+                                        "Cannot calculate the logarithm of a non positive number"
+                                    );
+                                    None
+                                } else if b <= 1 {
+                                    eprintln!("The base of a logarithm must be greater than 1");
+                                    None
+                                } else {
+                                    integer_log(a, &b)
+                                        .map(Rational::from)
+                                        .or_else(|| context.interrupt())
+                                }
+                            }
                             IntegerDiv => {
                                 if !b.is_zero() {
-                                    let (num, den) = (a / b).into_parts();
-                                    Some(Rational::from(num / den))
+                                    // Rounds toward negative infinity, not toward zero
+                                    Some(Rational::from(floor(a / b)))
                                 } else {
                                     eprintln!("Cannot divide by zero");
                                     None
@@ -307,23 +678,150 @@ impl ExecTree {
                             }
                             Exp => {
                                 //Flooring and converting to Int
-                                let mut a = a;
                                 let mut b = floor_abs(b, "Exponent", "exponentiation");
+
+                                // The result has at most bits(a)*b bits, on both sides of the fraction
+                                // 0, 1 and -1 never grow
+                                let (num, den) = a.clone().into_parts();
+                                let bits = Int::from(num.bit_length().max(den.bit_length()));
+                                if (num.abs() > 1 || den > 1) && &bits * &b > context.max_bits {
+                                    eprintln!("Result too large, max is {} bits", context.max_bits);
+                                    return None;
+                                }
+
                                 let mut result = Rational::one();
+                                // Squares of the same base are reused across the whole pass
+                                let squares =
+                                    context.powers.entry(a.clone()).or_insert_with(|| vec![a]);
+                                let mut i = 0;
                                 while !b.is_zero() {
+                                    // Only square when the cache doesn't reach this far
+                                    if i == squares.len() {
+                                        let square = &squares[i - 1] * &squares[i - 1];
+                                        squares.push(square);
+                                    }
                                     if !b.is_even() {
-                                        result *= &a;
+                                        result *= &squares[i];
                                     }
                                     b /= 2;
-                                    // Unfortunately we have to clone
-                                    // the size of a would double anyway
-                                    a *= a.clone();
+                                    i += 1;
                                 }
                                 Some(result)
                             }
+                            IeeeRemainder => {
+                                if !b.is_zero() {
+                                    // The quotient is rounded to the nearest integer
+                                    // so the result lies between -b/2 and b/2
+                                    let q = round_half_even(a.clone() / &b);
+                                    Some(a - b * q)
+                                } else {
+                                    eprintln!("Cannot divide by zero");
+                                    None
+                                }
+                            }
+                            Gcd => Some(Rational::from(gcd(
+                                floor_abs(a, "First operand", "gcd"),
+                                floor_abs(b, "Second operand", "gcd"),
+                            ))),
+                            Lcm => Some(Rational::from(lcm(
+                                floor_abs(a, "First operand", "lcm"),
+                                floor_abs(b, "Second operand", "lcm"),
+                            ))),
+                            // Comparisons return 1 for true and 0 for false, like ? expects
+                            Equal => Some(Rational::from((a == b) as u8)),
+                            NotEqual => Some(Rational::from((a != b) as u8)),
+                            Less => Some(Rational::from((a < b) as u8)),
+                            LessEqual => Some(Rational::from((a <= b) as u8)),
+                            Greater => Some(Rational::from((a > b) as u8)),
+                            GreaterEqual => Some(Rational::from((a >= b) as u8)),
+                            Hypotenuse => {
+                                let a = floor_abs(a, "First leg", "hypotenuse check");
+                                let b = floor_abs(b, "Second leg", "hypotenuse check");
+                                // The sum of squares is never negative, so sqrt_rem always works
+                                let (root, rem) = (a.square() + b.square()).sqrt_rem().unwrap();
+                                if rem.is_zero() {
+                                    Some(Rational::from(root))
+                                } else {
+                                    Some(Rational::zero())
+                                }
+                            }
+                            Binomial => binomial(
+                                &floor_abs(a, "First operand", "binomial coefficient"),
+                                &floor_abs(b, "Second operand", "binomial coefficient"),
+                            )
+                            .map(Rational::from)
+                            .or_else(|| context.interrupt()),
+                            Permutations => permutations(
+                                &floor_abs(a, "First operand", "permutations"),
+                                &floor_abs(b, "Second operand", "permutations"),
+                            )
+                            .map(Rational::from)
+                            .or_else(|| context.interrupt()),
+                            ModInverse => {
+                                // Flooring, the sign is kept because it changes the inverse
+                                if !a.clone().into_parts().1.is_one() {
+                                    eprintln!("Operand was not an integer in modular inverse");
+                                }
+                                let a = floor(a);
+                                let m = floor_abs(b, "Modulo", "modular inverse");
+                                if m.is_zero() {
+                                    eprintln!("Modulo cannot be zero");
+                                    None
+                                } else if let Some(inverse) = mod_inverse(&a, &m) {
+                                    Some(Rational::from(inverse))
+                                } else {
+                                    eprintln!("{} has no inverse in modulo {}", a, m);
+                                    None
+                                }
+                            }
+                            BitAnd => Some(Rational::from(
+                                floor_abs(a, "First operand", "bitwise and")
+                                    & floor_abs(b, "Second operand", "bitwise and"),
+                            )),
+                            BitOr => Some(Rational::from(
+                                floor_abs(a, "First operand", "bitwise or")
+                                    | floor_abs(b, "Second operand", "bitwise or"),
+                            )),
+                            BitXor => Some(Rational::from(
+                                floor_abs(a, "First operand", "bitwise xor")
+                                    ^ floor_abs(b, "Second operand", "bitwise xor"),
+                            )),
+                            ShiftLeft => {
+                                let a = floor_abs(a, "Operand", "left shift");
+                                let b = floor_abs(b, "Shift count", "left shift");
+                                // Every bit of shift doubles the size of the result
+                                if MAX_SHIFT < b {
+                                    eprintln!("Shift count is too big, max is {}", MAX_SHIFT);
+                                    None
+                                } else {
+                                    Some(Rational::from(a << usize::from(&b)))
+                                }
+                            }
+                            Tetration => {
+                                let a = floor_abs(a, "Base", "tetration");
+                                let b = floor_abs(b, "Height", "tetration");
+                                let result = tetration(&a, &b, context.max_bits);
+                                if result.is_none() {
+                                    eprintln!("Result too large, max is {} bits", context.max_bits);
+                                }
+                                result.map(Rational::from)
+                            }
+                            ShiftRight => {
+                                let a = floor_abs(a, "Operand", "right shift");
+                                let b = floor_abs(b, "Shift count", "right shift");
+                                // Shifting out all the bits (or more) always gives 0
+                                if Int::from(a.bit_length()) <= b {
+                                    Some(Rational::zero())
+                                } else {
+                                    Some(Rational::from(a >> usize::from(&b)))
+                                }
+                            }
 
                             // All the other tokens will never enter the tree
-                            _ => panic!("Corrupted stack"),
+                            token => {
+                                eprintln!("Corrupted stack, '{}' can't be computed", token);
+                                None
+                            }
                         }
                     } else {
                         // Return None if an argument didn't compute
@@ -335,16 +833,645 @@ impl ExecTree {
     }
 }
 
+impl ExecTree {
+    // Computes the tree one operation at a time, as a stack machine would,
+    // writing the stack after each operation
+    // Like in reduce, If, And, and Or only compute the arguments they need
+    pub fn derive(
+        &self,
+        table: &HashMap<String, Object>,
+        context: &mut Context,
+        stack: &mut Vec<Rational>,
+        out: &mut dyn Write,
+    ) -> Option<()> {
+        // Operands go straight in the stack
+        if self.arguments.is_empty() {
+            stack.push(self.reduce(table, &Vec::new(), context)?);
+            return Some(());
+        }
+
+        match self.token {
+            // The condition is replaced by the arm it chooses
+            If => {
+                self.arguments[2].derive(table, context, stack, out)?;
+                let arm = if stack.pop()?.is_zero() { 1 } else { 0 };
+                self.arguments[arm].derive(table, context, stack, out)?;
+            }
+            // The second argument is only computed if the first doesn't decide the result
+            And | Or => {
+                self.arguments[0].derive(table, context, stack, out)?;
+                let first = stack.pop()?;
+                let result = if first.is_zero() == (self.token == And) {
+                    self.token == Or
+                } else {
+                    self.arguments[1].derive(table, context, stack, out)?;
+                    !stack.pop()?.is_zero()
+                };
+                stack.push(Rational::from(result as u8));
+            }
+            _ => {
+                // Post-order, the arguments are left on top of the stack
+                for argument in &self.arguments {
+                    argument.derive(table, context, stack, out)?;
+                }
+
+                // Replace the arguments with their values, and apply the operation
+                let len = stack.len();
+                let node = ExecTree {
+                    token: self.token.clone(),
+                    arguments: stack
+                        .split_off(len - self.arguments.len())
+                        .into_iter()
+                        .map(|value| ExecTree {
+                            token: Number(value),
+                            arguments: Vec::new(),
+                        })
+                        .collect(),
+                };
+                stack.push(node.reduce(table, &Vec::new(), context)?);
+            }
+        }
+
+        // Write the operation and the resulting stack
+        write!(out, "{} ->", self.token).ok()?;
+        for value in stack.iter() {
+            write!(out, " {}", Number(value.clone())).ok()?;
+        }
+        writeln!(out).ok()?;
+        Some(())
+    }
+
+    // Computes the tree like reduce, writing every operation with its operands and result
+    // Like in reduce, If, And, and Or only compute the arguments they need,
+    // function calls are computed in a single step
+    pub fn trace(
+        &self,
+        table: &HashMap<String, Object>,
+        context: &mut Context,
+        out: &mut dyn Write,
+    ) -> Option<Rational> {
+        // Operands don't need to be written
+        if self.arguments.is_empty() {
+            return self.reduce(table, &Vec::new(), context);
+        }
+
+        let values = match self.token {
+            If => {
+                let condition = self.arguments[2].trace(table, context, out)?;
+                let arm = if condition.is_zero() { 1 } else { 0 };
+                let value = self.arguments[arm].trace(table, context, out)?;
+                writeln!(out, "{} ? -> {}", Number(condition), Number(value.clone())).ok()?;
+                return Some(value);
+            }
+            // If the first argument decides the result, the second isn't computed
+            And | Or => {
+                let first = self.arguments[0].trace(table, context, out)?;
+                if first.is_zero() == (self.token == And) {
+                    let result = Rational::from((self.token == Or) as u8);
+                    writeln!(
+                        out,
+                        "{} {} -> {}",
+                        Number(first),
+                        self.token,
+                        Number(result.clone())
+                    )
+                    .ok()?;
+                    return Some(result);
+                }
+                vec![first, self.arguments[1].trace(table, context, out)?]
+            }
+            _ => {
+                let mut values = Vec::new();
+                for argument in &self.arguments {
+                    values.push(argument.trace(table, context, out)?);
+                }
+                values
+            }
+        };
+
+        // Replace the arguments with their values, and apply the operation
+        let node = ExecTree {
+            token: self.token.clone(),
+            arguments: values
+                .iter()
+                .map(|value| ExecTree {
+                    token: Number(value.clone()),
+                    arguments: Vec::new(),
+                })
+                .collect(),
+        };
+        let result = node.reduce(table, &Vec::new(), context)?;
+
+        for value in values {
+            write!(out, "{} ", Number(value)).ok()?;
+        }
+        writeln!(out, "{} -> {}", self.token, Number(result.clone())).ok()?;
+        Some(result)
+    }
+
+    // Writes the tree back as rpn-l, in postfix order
+    pub fn to_rpn(&self) -> String {
+        let mut tokens = Vec::new();
+        self.postfix(&mut tokens);
+        tokens.join(" ")
+    }
+
+    fn postfix(&self, tokens: &mut Vec<String>) {
+        for argument in &self.arguments {
+            argument.postfix(tokens);
+        }
+        tokens.push(self.token.to_string());
+    }
+
+    // Collects the names used in the tree
+    pub fn identifiers<'a>(&'a self, names: &mut Vec<&'a str>) {
+        if let Identifier(name) = &self.token {
+            names.push(name);
+        }
+        for argument in &self.arguments {
+            argument.identifiers(names);
+        }
+    }
+
+    // Prints the tree without computing it,
+    // one node per line, children are indented under their parent
+    pub fn print(&self, depth: usize, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}{}", "  ".repeat(depth), self.token)?;
+        for argument in &self.arguments {
+            argument.print(depth + 1, out)?;
+        }
+        Ok(())
+    }
+}
+
 #[inline]
 fn run_function(
     ops: &ExecTree,
     args: &Vec<Option<Rational>>,
     table: &HashMap<String, Object>,
+    context: &mut Context,
 ) -> Option<Rational> {
     // Check if some arguments didn't compute
     if args.iter().filter(|arg| arg.is_none()).count() > 0 {
         return None;
     }
     // Execute tree
-    ops.reduce(table, args)
+    ops.reduce(table, args, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use logos::Logos;
+
+    // Computes a single expression without definitions, as it would be printed
+    fn compute(source: &str) -> Option<String> {
+        let table = HashMap::new();
+        parse_tree(Token::lexer(source).collect(), &table)
+            .ok()?
+            .reduce(&table, &Vec::new(), &mut Context::new())
+            .map(|value| Number(value).to_string())
+    }
+
+    fn tree(source: &str) -> ExecTree {
+        parse_tree(Token::lexer(source).collect(), &HashMap::new()).unwrap()
+    }
+
+    // Sum of base^e for every exponent, as a single expression
+    fn polynomial(base: &str, exponents: &[usize]) -> String {
+        let mut terms: Vec<String> = exponents
+            .iter()
+            .map(|exponent| format!("{} {} ^", base, exponent))
+            .collect();
+        let first = terms.remove(0);
+        terms
+            .into_iter()
+            .fold(first, |sum, term| format!("{} {} +", sum, term))
+    }
+
+    // Computes the terms of a polynomial one at a time, so no squares are shared
+    fn uncached_polynomial(base: &str, exponents: &[usize]) -> Rational {
+        exponents.iter().fold(Rational::zero(), |sum, exponent| {
+            sum + tree(&format!("{} {} ^", base, exponent))
+                .reduce(&HashMap::new(), &Vec::new(), &mut Context::new())
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn ieee_remainder_rounds_the_quotient_to_nearest() {
+        assert_eq!(compute("7 3 ieeerem").as_deref(), Some("1"));
+        assert_eq!(compute("8 3 ieeerem").as_deref(), Some("-1"));
+    }
+
+    #[test]
+    fn ieee_remainder_signs() {
+        assert_eq!(compute("-7 3 ieeerem").as_deref(), Some("-1"));
+        assert_eq!(compute("7 -3 ieeerem").as_deref(), Some("1"));
+        assert_eq!(compute("-8 -3 ieeerem").as_deref(), Some("1"));
+        // Ties go to the even quotient
+        assert_eq!(compute("5 2 ieeerem").as_deref(), Some("1"));
+        assert_eq!(compute("7 2 ieeerem").as_deref(), Some("-1"));
+        assert_eq!(compute("7/2 1 ieeerem").as_deref(), Some("-1/2"));
+        assert_eq!(compute("7 0 ieeerem"), None);
+    }
+
+    #[test]
+    fn gcd_and_lcm_floor_their_operands() {
+        assert_eq!(compute("12 18 gcd").as_deref(), Some("6"));
+        assert_eq!(compute("12 18 lcm").as_deref(), Some("36"));
+        assert_eq!(compute("-12 0 gcd").as_deref(), Some("12"));
+        assert_eq!(compute("25/2 18 gcd").as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn exp_squares_cache_matches_uncached() {
+        let exponents: Vec<usize> = (0..40).map(|k| k * 7 + k % 3).collect();
+        for base in ["3", "-2/3", "1", "0", "-1"].iter() {
+            let mut context = Context::new();
+            let cached = tree(&polynomial(base, &exponents))
+                .reduce(&HashMap::new(), &Vec::new(), &mut context)
+                .unwrap();
+            assert_eq!(cached, uncached_polynomial(base, &exponents));
+            // The squares stay in the context until it's cleared
+            assert!(!context.powers.is_empty());
+            context.clear();
+            assert!(context.powers.is_empty());
+        }
+    }
+
+    // Run with: cargo test bench -- --ignored --nocapture
+    // the squares are shared by all the terms, so the more the terms have in common the bigger the gain
+    #[test]
+    #[ignore]
+    fn bench_exp_squares_cache() {
+        let cases: [(&str, Vec<usize>); 3] = [
+            ("distinct exponents", (1..=200).map(|k| k * 97).collect()),
+            ("repeated exponents", vec![4095; 200]),
+            ("repeated power of two", vec![4096; 200]),
+        ];
+        for (name, exponents) in cases.iter() {
+            let polynomial = tree(&polynomial("7/3", exponents));
+
+            let start = std::time::Instant::now();
+            let cached = polynomial.reduce(&HashMap::new(), &Vec::new(), &mut Context::new());
+            let cached_time = start.elapsed();
+
+            let start = std::time::Instant::now();
+            let uncached = uncached_polynomial("7/3", exponents);
+            let uncached_time = start.elapsed();
+
+            assert_eq!(cached, Some(uncached));
+            println!(
+                "{}: cached {:?}, uncached {:?}",
+                name, cached_time, uncached_time
+            );
+        }
+    }
+
+    #[test]
+    fn hypotenuse_of_integer_legs() {
+        assert_eq!(compute("3 4 ishyp").as_deref(), Some("5"));
+        assert_eq!(compute("2 3 ishyp").as_deref(), Some("0"));
+        assert_eq!(compute("-5 12 ishyp").as_deref(), Some("13"));
+        assert_eq!(compute("0 0 ishyp").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn decimal_literals_are_exact() {
+        assert_eq!(compute("0.1 0.2 +").as_deref(), Some("3/10"));
+        assert_eq!(compute("0.1 0.2 + 0.3 eq").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn decimal_period_length() {
+        assert_eq!(compute("1 7 / period").as_deref(), Some("6"));
+        assert_eq!(compute("1 8 / period").as_deref(), Some("0"));
+        assert_eq!(compute("5 period").as_deref(), Some("0"));
+        assert_eq!(compute("1/12 period").as_deref(), Some("1"));
+        assert_eq!(compute("-22/7 period").as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn comparisons_select_the_branch() {
+        assert_eq!(compute("10 20 3 2 gt ?").as_deref(), Some("10"));
+        assert_eq!(compute("10 20 2 3 gt ?").as_deref(), Some("20"));
+        for (comparison, results) in [
+            ("eq", ["0", "1", "0"]),
+            ("ne", ["1", "0", "1"]),
+            ("lt", ["1", "0", "0"]),
+            ("le", ["1", "1", "0"]),
+            ("gt", ["0", "0", "1"]),
+            ("ge", ["0", "1", "1"]),
+        ]
+        .iter()
+        {
+            for (operands, result) in ["1/2 2/3", "2/4 1/2", "1 -1"].iter().zip(results.iter()) {
+                let source = format!("{} {}", operands, comparison);
+                assert_eq!(compute(&source).as_deref(), Some(*result), "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn abs_and_neg() {
+        assert_eq!(compute("-5 abs").as_deref(), Some("5"));
+        assert_eq!(compute("-3/4 abs").as_deref(), Some("3/4"));
+        assert_eq!(compute("3/4 neg").as_deref(), Some("-3/4"));
+        assert_eq!(compute("-3/4 neg").as_deref(), Some("3/4"));
+        assert_eq!(compute("0 neg").as_deref(), Some("0"));
+    }
+
+    // Run with: cargo test bench -- --ignored --nocapture
+    // reduce is sequential, this is the cost of a tiny expression without any thread pool
+    #[test]
+    #[ignore]
+    fn bench_small_expressions() {
+        let trees: Vec<ExecTree> = (0..10000)
+            .map(|i| tree(&format!("{} 3 + {} *", i, i)))
+            .collect();
+        let start = std::time::Instant::now();
+        let results: Vec<Option<Rational>> = trees
+            .iter()
+            .map(|tree| tree.reduce(&HashMap::new(), &Vec::new(), &mut Context::new()))
+            .collect();
+        let elapsed = start.elapsed();
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result, Some(Rational::from((i as u32 + 3) * i as u32)));
+        }
+        println!(
+            "{} expressions in {:?}, {:?} each",
+            trees.len(),
+            elapsed,
+            elapsed / trees.len() as u32
+        );
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        assert_eq!(compute("12 10 band").as_deref(), Some("8"));
+        assert_eq!(compute("12 10 bor").as_deref(), Some("14"));
+        assert_eq!(compute("12 10 bxor").as_deref(), Some("6"));
+        // Operands are floored and made positive
+        assert_eq!(compute("-12 10 band").as_deref(), Some("8"));
+        assert_eq!(compute("25/2 -21/2 bor").as_deref(), Some("14"));
+    }
+
+    #[test]
+    fn bit_shifts() {
+        assert_eq!(compute("1 8 shl").as_deref(), Some("256"));
+        assert_eq!(compute("256 4 shr").as_deref(), Some("16"));
+        assert_eq!(compute("256 9 shr").as_deref(), Some("0"));
+        assert_eq!(compute("256 100000000000 shr").as_deref(), Some("0"));
+        // Too many bits to allocate
+        assert_eq!(compute("1 100000000000 shl"), None);
+    }
+
+    #[test]
+    fn sign_of_numbers() {
+        assert_eq!(compute("-7/3 sign").as_deref(), Some("-1"));
+        assert_eq!(compute("0 sign").as_deref(), Some("0"));
+        assert_eq!(compute("5 sign").as_deref(), Some("1"));
+        assert_eq!(compute("1/1000 0 - sign").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn floor_and_ceil_keep_the_sign() {
+        assert_eq!(compute("-3/2 floor").as_deref(), Some("-2"));
+        assert_eq!(compute("-3/2 ceil").as_deref(), Some("-1"));
+        assert_eq!(compute("3/2 floor").as_deref(), Some("1"));
+        assert_eq!(compute("3/2 ceil").as_deref(), Some("2"));
+        assert_eq!(compute("-1/2 ceil").as_deref(), Some("0"));
+        assert_eq!(compute("-4 floor").as_deref(), Some("-4"));
+        assert_eq!(compute("4 ceil").as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn round_ties_to_even() {
+        assert_eq!(compute("1/2 round").as_deref(), Some("0"));
+        assert_eq!(compute("3/2 round").as_deref(), Some("2"));
+        assert_eq!(compute("5/2 round").as_deref(), Some("2"));
+        assert_eq!(compute("7/2 round").as_deref(), Some("4"));
+        assert_eq!(compute("-5/2 round").as_deref(), Some("-2"));
+        assert_eq!(compute("-7/2 round").as_deref(), Some("-4"));
+        assert_eq!(compute("5/3 round").as_deref(), Some("2"));
+        assert_eq!(compute("-4/3 round").as_deref(), Some("-1"));
+    }
+
+    #[test]
+    fn integer_division_floors() {
+        assert_eq!(compute(r"-7 2 \").as_deref(), Some("-4"));
+        assert_eq!(compute(r"7 -2 \").as_deref(), Some("-4"));
+        assert_eq!(compute(r"-7 -2 \").as_deref(), Some("3"));
+        assert_eq!(compute(r"7 2 \").as_deref(), Some("3"));
+        assert_eq!(compute(r"-6 2 \").as_deref(), Some("-3"));
+        assert_eq!(compute(r"-7/2 1/2 \").as_deref(), Some("-7"));
+        assert_eq!(compute(r"1 0 \"), None);
+    }
+
+    #[test]
+    fn numerator_and_denominator_are_reduced() {
+        assert_eq!(compute("6/4 numer").as_deref(), Some("3"));
+        assert_eq!(compute("6/4 denom").as_deref(), Some("2"));
+        assert_eq!(compute("-6/4 numer").as_deref(), Some("-3"));
+        assert_eq!(compute("-6/4 denom").as_deref(), Some("2"));
+        assert_eq!(compute("5 denom").as_deref(), Some("1"));
+        assert_eq!(compute("0 denom").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn modular_inverse() {
+        assert_eq!(compute("3 11 modinv").as_deref(), Some("4"));
+        assert_eq!(compute("-3 11 modinv").as_deref(), Some("7"));
+        // -7/2 is floored to -4, not truncated to -3
+        assert_eq!(compute("-7/2 11 modinv").as_deref(), Some("8"));
+        assert_eq!(compute("7/2 -11 modinv").as_deref(), Some("4"));
+        assert_eq!(compute("6 9 modinv"), None);
+        assert_eq!(compute("3 0 modinv"), None);
+    }
+
+    #[test]
+    fn tetration_with_size_guard() {
+        assert_eq!(compute("2 3 tetr").as_deref(), Some("16"));
+        assert_eq!(compute("2 4 tetr").as_deref(), Some("65536"));
+        assert_eq!(compute("0 3 tetr").as_deref(), Some("0"));
+        assert_eq!(compute("5 0 tetr").as_deref(), Some("1"));
+        // 3^3^3^3 has about 10^13 bits
+        assert_eq!(compute("3 4 tetr"), None);
+    }
+
+    #[test]
+    fn monus_and_absolute_difference() {
+        assert_eq!(compute("5 3 ~").as_deref(), Some("2"));
+        assert_eq!(compute("3 5 ~").as_deref(), Some("0"));
+        assert_eq!(compute("7/2 1/3 ~").as_deref(), Some("19/6"));
+        assert_eq!(compute("1/3 7/2 ~").as_deref(), Some("0"));
+        assert_eq!(compute("5 3 absdiff").as_deref(), Some("2"));
+        assert_eq!(compute("3 5 absdiff").as_deref(), Some("2"));
+        assert_eq!(compute("1/3 7/2 absdiff").as_deref(), Some("19/6"));
+    }
+
+    #[test]
+    fn integer_logarithm() {
+        assert_eq!(compute("1000 10 ilog").as_deref(), Some("3"));
+        assert_eq!(compute("999 10 ilog").as_deref(), Some("2"));
+        assert_eq!(compute("255 2 ilog").as_deref(), Some("7"));
+        assert_eq!(compute("1 2 ilog").as_deref(), Some("0"));
+        assert_eq!(compute("5 1 ilog"), None);
+        assert_eq!(compute("0 2 ilog"), None);
+    }
+
+    #[test]
+    fn digit_count_and_sum() {
+        assert_eq!(compute("12345 digits").as_deref(), Some("5"));
+        assert_eq!(compute("12345 digitsum").as_deref(), Some("15"));
+        assert_eq!(compute("0 digits").as_deref(), Some("1"));
+        // 10^99 has 100 digits, 10^100 - 1 has 100 nines
+        assert_eq!(compute("10 99 ^ digits").as_deref(), Some("100"));
+        assert_eq!(compute("10 100 ^ 1 - digitsum").as_deref(), Some("900"));
+    }
+
+    #[test]
+    fn reciprocal_keeps_the_sign() {
+        assert_eq!(compute("3/4 recip").as_deref(), Some("4/3"));
+        assert_eq!(compute("-2 recip").as_deref(), Some("-1/2"));
+        assert_eq!(compute("0 recip"), None);
+    }
+
+    #[test]
+    fn logical_not() {
+        assert_eq!(compute("5 not").as_deref(), Some("0"));
+        assert_eq!(compute("-1/2 not").as_deref(), Some("0"));
+        assert_eq!(compute("0 not").as_deref(), Some("1"));
+        assert_eq!(compute("0 not not").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn logical_and_or() {
+        assert_eq!(compute("2 3 and").as_deref(), Some("1"));
+        assert_eq!(compute("2 0 and").as_deref(), Some("0"));
+        assert_eq!(compute("0 0 or").as_deref(), Some("0"));
+        assert_eq!(compute("0 -5 or").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn logical_and_or_short_circuit() {
+        // y is undefined, computing it would fail
+        assert_eq!(compute("0 y and").as_deref(), Some("0"));
+        assert_eq!(compute("1 y or").as_deref(), Some("1"));
+        assert_eq!(compute("0 1 0 / and").as_deref(), Some("0"));
+        assert_eq!(compute("1 y and"), None);
+        assert_eq!(compute("0 y or"), None);
+    }
+
+    #[test]
+    fn clamp_between_bounds() {
+        assert_eq!(compute("5 0 10 clamp").as_deref(), Some("5"));
+        assert_eq!(compute("-3 0 10 clamp").as_deref(), Some("0"));
+        assert_eq!(compute("15 0 10 clamp").as_deref(), Some("10"));
+        assert_eq!(compute("1/2 0 1 clamp").as_deref(), Some("1/2"));
+    }
+
+    #[test]
+    fn between_includes_the_bounds() {
+        assert_eq!(compute("5 5 10 between").as_deref(), Some("1"));
+        assert_eq!(compute("10 5 10 between").as_deref(), Some("1"));
+        assert_eq!(compute("7/2 3 4 between").as_deref(), Some("1"));
+        assert_eq!(compute("11 5 10 between").as_deref(), Some("0"));
+        assert_eq!(compute("4 5 10 between").as_deref(), Some("0"));
+        // An empty range holds nothing
+        assert_eq!(compute("7 10 5 between").as_deref(), Some("0"));
+    }
+
+    // There is no thread pool to tune, the arguments of reduce are evaluated one after the other
+    // this is the throughput of naive recursion, where every call has two tiny arguments
+    #[test]
+    #[ignore]
+    fn bench_naive_recursion() {
+        let mut table = HashMap::new();
+        // The arity of nfib must be known in advance to parse its recursive calls
+        table.insert(String::from("nfib"), Function(1, tree("0")));
+        let body = parse_tree(
+            Token::lexer("$0 $0 1 - nfib $0 2 - nfib + $0 2 lt ?").collect(),
+            &table,
+        )
+        .unwrap();
+        table.insert(String::from("nfib"), Function(1, body));
+
+        let start = std::time::Instant::now();
+        let result = parse_tree(Token::lexer("25 nfib").collect(), &table)
+            .unwrap()
+            .reduce(&table, &Vec::new(), &mut Context::new());
+        let elapsed = start.elapsed();
+        assert_eq!(result, Some(Rational::from(75025)));
+        // nfib(25) makes 2 * fib(26) - 1 calls
+        println!("242785 calls in {:?}, {:?} each", elapsed, elapsed / 242785);
+    }
+
+    #[test]
+    fn collatz_steps() {
+        assert_eq!(compute("27 collatz").as_deref(), Some("111"));
+        assert_eq!(compute("1 collatz").as_deref(), Some("0"));
+        assert_eq!(compute("55/2 collatz").as_deref(), Some("111"));
+        // A power of two only halves
+        assert_eq!(compute("2 100 ^ collatz").as_deref(), Some("100"));
+        assert_eq!(compute("0 collatz"), None);
+        assert_eq!(compute("-5 collatz"), None);
+    }
+
+    #[test]
+    fn exp_result_size_guard() {
+        assert_eq!(compute("2 10 ^").as_deref(), Some("1024"));
+        assert_eq!(compute("1/2 -10 ^").as_deref(), Some("1/1024"));
+        assert_eq!(compute("10 1000000000 ^"), None);
+        assert_eq!(compute("1/10 -1000000000 ^"), None);
+        // Results that don't grow are never too large
+        assert_eq!(compute("1 1000000000 ^").as_deref(), Some("1"));
+        assert_eq!(compute("-1 1000000001 ^").as_deref(), Some("-1"));
+        assert_eq!(compute("0 1000000000 ^").as_deref(), Some("0"));
+    }
+
+    // The error of parsing source, with the functions in table
+    fn parse_error(source: &str, table: &HashMap<String, Object>) -> Option<String> {
+        parse_tree(Token::lexer(source).collect(), table).err()
+    }
+
+    #[test]
+    fn parse_errors_name_the_token() {
+        let mut table = HashMap::new();
+        assert_eq!(
+            parse_error("2 +", &table).as_deref(),
+            Some("operator '+' needs 2 arguments but found 1")
+        );
+        assert_eq!(
+            parse_error("1 2 ?", &table).as_deref(),
+            Some("operator '?' needs 3 arguments but found 2")
+        );
+        // The first token that misses arguments is reported
+        assert_eq!(
+            parse_error("neg 1 +", &table).as_deref(),
+            Some("operator 'neg' needs 1 arguments but found 0")
+        );
+        table.insert(String::from("add"), Function(2, tree("$0 $1 +")));
+        assert_eq!(
+            parse_error("1 add", &table).as_deref(),
+            Some("function 'add' needs 2 arguments but found 1")
+        );
+        assert_eq!(parse_error("", &table).as_deref(), Some("Empty expression"));
+        assert_eq!(
+            parse_error("1 =", &table).as_deref(),
+            Some("'=' can't be part of an expression")
+        );
+        assert_eq!(parse_error("1 2 add", &table), None);
+    }
+
+    #[test]
+    fn print_writes_to_the_output() {
+        let mut out = Vec::new();
+        tree("2 3 + 4 *").print(0, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "*\n  +\n    2\n    3\n  4\n"
+        );
+    }
 }
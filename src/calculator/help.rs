@@ -0,0 +1,144 @@
+// Table printed by the help command
+// One row per token: symbol, name, arity, description
+// The arity is the number of expressions the token takes from the stack
+#[rustfmt::skip]
+pub static OPERATORS: &[(&str, &str, &str, &str)] = &[
+    ("+", "plus", "2", "sum"),
+    ("-", "minus", "2", "difference"),
+    ("*", "times", "2", "product"),
+    ("/", "divide", "2", "quotient"),
+    ("~", "positive minus", "2", "difference, or 0 if it's negative"),
+    ("absdiff", "absolute difference", "2", "|$0 - $1|"),
+    ("\\", "integer division", "2", "quotient floored toward negative infinity"),
+    ("^", "exp", "2", "power, the exponent is floored and made positive"),
+    ("_", "exp mod", "3", "power in modulo $2"),
+    ("ieeerem", "ieee remainder", "2", "remainder of the quotient rounded to nearest"),
+    ("ilog", "integer logarithm", "2", "floor of the logarithm of $0 in base $1"),
+    ("gcd", "gcd", "2", "greatest common divisor"),
+    ("lcm", "lcm", "2", "least common multiple"),
+    ("ishyp", "hypotenuse", "2", "integer hypotenuse of the two legs, or 0"),
+    ("modinv", "modular inverse", "2", "inverse of $0 in modulo $1"),
+    ("band", "bitwise and", "2", "bitwise and"),
+    ("bor", "bitwise or", "2", "bitwise or"),
+    ("bxor", "bitwise xor", "2", "bitwise xor"),
+    ("shl", "shift left", "2", "shifts the bits of $0 left by $1"),
+    ("shr", "shift right", "2", "shifts the bits of $0 right by $1"),
+    ("tetr", "tetration", "2", "power tower of $1 copies of $0"),
+    ("eq", "equal", "2", "1 if $0 = $1, 0 otherwise"),
+    ("ne", "not equal", "2", "1 if $0 != $1, 0 otherwise"),
+    ("lt", "less", "2", "1 if $0 < $1, 0 otherwise"),
+    ("le", "less or equal", "2", "1 if $0 <= $1, 0 otherwise"),
+    ("gt", "greater", "2", "1 if $0 > $1, 0 otherwise"),
+    ("ge", "greater or equal", "2", "1 if $0 >= $1, 0 otherwise"),
+    ("nCr", "binomial", "2", "combinations of $1 elements out of $0"),
+    ("nPr", "permutations", "2", "permutations of $1 elements out of $0"),
+    ("period", "period", "1", "length of the repeating part of the decimals"),
+    ("abs", "abs", "1", "absolute value"),
+    ("neg", "negate", "1", "opposite"),
+    ("fact", "factorial", "1", "factorial"),
+    ("fib", "fibonacci", "1", "($0+1)-th Fibonacci number"),
+    ("isprime", "prime", "1", "1 if prime, 0 otherwise"),
+    ("sign", "sign", "1", "1, 0 or -1"),
+    ("floor", "floor", "1", "rounds down"),
+    ("ceil", "ceil", "1", "rounds up"),
+    ("round", "round", "1", "rounds to nearest, ties to even"),
+    ("digits", "digits", "1", "number of decimal digits"),
+    ("digitsum", "digit sum", "1", "sum of the decimal digits"),
+    ("recip", "reciprocal", "1", "1 / $0"),
+    ("numer", "numerator", "1", "numerator in lowest terms"),
+    ("denom", "denominator", "1", "denominator in lowest terms"),
+    ("not", "not", "1", "1 if $0 is 0, 0 otherwise"),
+    ("collatz", "collatz", "1", "steps of the Collatz map to bring $0 to 1"),
+    ("and", "and", "2", "1 if both are not 0, $1 is skipped if $0 is 0"),
+    ("or", "or", "2", "1 if one is not 0, $1 is skipped if $0 is not 0"),
+    ("clamp", "clamp", "3", "$0 limited between $1 and $2"),
+    ("between", "between", "3", "1 if $1 <= $0 <= $2, 0 otherwise"),
+    ("?", "if", "3", "$0 if $2 is not 0, $1 otherwise"),
+    ("$n", "argument", "0", "n-th argument of the function"),
+    ("=", "return", "1", "computes and prints"),
+    ("#", "partial", "1", "computes, prints, and pushes the result back"),
+    (":", "print", "0", "prints the stack"),
+    (">", "flush", "all", "computes and prints the whole stack"),
+    ("<", "duplicate", "1", "computes and pushes the result twice"),
+    ("!", "drop", "1", "drops the top of the stack"),
+    ("%", "empty", "all", "drops the whole stack"),
+    ("&", "format", "1", "computes and prints as a string"),
+    ("decode", "decode", "1", "computes and prints as a string literal"),
+    ("[]", "approx", "1", "computes and prints as a floating point"),
+    ("[n]", "decimal", "1", "computes and prints with n decimal digits"),
+    ("weightedsum", "weighted sum", "all", "sum of the values times their weights"),
+    ("sum", "sum", "all", "sum of the values"),
+    ("avg", "average", "all", "average of the values"),
+    ("steps", "steps", "1", "prints each step of the computation"),
+    ("tree", "tree", "1", "prints the execution tree"),
+    ("trace", "trace", "1", "computes and prints, with each operation"),
+    ("time", "time", "1", "computes and prints, with the time it took"),
+    ("profile", "profile", "1", "computes and prints, with the reduced nodes"),
+    ("mixed", "mixed", "1", "computes and prints as a mixed number"),
+    ("parts", "parts", "1", "computes and prints numerator and denominator"),
+    ("cfrac", "continued fraction", "1", "computes and prints as a continued fraction"),
+    ("hex", "hex", "1", "computes and prints in hexadecimal"),
+    ("depth", "depth", "0", "pushes the number of expressions in stack"),
+    ("clearbut", "clear but", "1", "drops the stack except the top"),
+    ("divmod", "divmod", "2", "pushes the floored quotient and the remainder"),
+    ("repeat", "repeat", "3", "pushes $0 after applying $2 to it $1 times"),
+    ("pick", "pick", "n+1", "copies the n-th expression on top"),
+    ("roll", "roll", "n+1", "moves the n-th expression on top"),
+    ("select", "select", "n+2", "keeps the $n-th of the n expressions under it"),
+    ("maxdepth", "max depth", "1", "sets the recursion limit"),
+    ("maxsteps", "max steps", "1", "sets the operations limit of a computation"),
+    ("maxbits", "max bits", "1", "sets the size limit of ^, tetr and fib"),
+    ("memo", "memoize", "0", "toggles memoization of function calls"),
+    ("group", "group", "0", "toggles digit grouping"),
+    ("decimals", "decimals", "1", "prints the results with $0 decimal digits"),
+    ("fractions", "fractions", "0", "prints the results as fractions"),
+    ("stackreport", "stack report", "0", "toggles the number of elements in stack"),
+    ("trackminmax", "track min max", "0", "toggles tracking of the pushed numbers"),
+    ("minmax", "min max", "0", "prints the tracked numbers"),
+    ("resetminmax", "reset min max", "0", "forgets the tracked numbers"),
+    ("save:<file>", "save", "0", "writes the session to <file>"),
+    ("dump", "dump", "0", "prints the definitions as a script"),
+    ("output:<file>", "output", "0", "writes the results to <file>"),
+    ("close", "close", "0", "writes the results to stdout again"),
+    ("check:f|n", "check", "all", "reports if the declaration of f is complete"),
+    ("help", "help", "0", "prints this table"),
+    ("reload", "reload", "0", "loads the std_lib again"),
+    ("version", "version", "0", "prints the version of rpn-c"),
+];
+
+// Syntaxes for defining and removing names
+#[rustfmt::skip]
+pub static ASSIGNMENTS: &[(&str, &str)] = &[
+    ("<exp> =x", "assigns the value of <exp> to the variable x"),
+    ("<exp> :=x", "like =x, but leaves the value in stack"),
+    ("<exp> f|n", "declares the function f of arity n"),
+    ("<exp0> ... <expn+1> f@n", "declares the iterative function f of arity n"),
+    ("<target> <lo> <hi> <tolerance> f^-1", "searches the inverse of f by bisection"),
+    ("-x", "removes x from the table"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::super::Token;
+    use super::*;
+    use logos::Logos;
+
+    #[test]
+    fn every_symbol_is_a_single_token() {
+        for (symbol, ..) in OPERATORS {
+            // Placeholders replaced with an example
+            let example = symbol
+                .replace("$n", "$0")
+                .replace("[n]", "[2]")
+                .replace("<file>", "x")
+                .replace("f|n", "f|1");
+            let tokens: Vec<Token> = Token::lexer(&example).collect();
+            assert_eq!(tokens.len(), 1, "{} is not a single token", symbol);
+            assert!(
+                !matches!(tokens[0], Token::Error | Token::Identifier(_)),
+                "{} is not an operator",
+                symbol
+            );
+        }
+    }
+}
@@ -1,18 +1,27 @@
 use execution::*;
+use help::*;
 use logos::Logos;
 use num_traits::{One, Zero};
 use ramp::rational::Rational;
-use std::collections::HashMap;
+use ramp::Int;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::io::Write;
+use std::fs::{read_to_string, File};
+use std::io::{stdout, Write};
+use std::path::PathBuf;
 use std::string::String;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 use strings::*;
 use utils::*;
 use Found::*;
 use Object::*;
 use Token::*;
 
+pub use execution::INTERRUPTED;
+
 mod execution;
+mod help;
 mod strings;
 mod utils;
 
@@ -25,12 +34,25 @@ pub enum Token {
     #[regex("=[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*", |lex| String::from(lex.slice()))]
     AssignVariable(String),
 
-    #[regex("[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*\\|[0-9]+", |lex| String::from(lex.slice()))]
+    #[regex(":=[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*", |lex| String::from(&lex.slice()[2..]))]
+    KeepVariable(String),
+
+    #[regex("-[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*", |lex| String::from(lex.slice()))]
+    Undefine(String),
+
+    // The arity must fit in a usize, otherwise it's an error
+    #[regex("[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*\\|[0-9]+", |lex| with_arity(lex.slice()))]
     AssignFunction(String),
 
-    #[regex("[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*@[0-9]+", |lex| String::from(lex.slice()))]
+    #[regex("[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*@[0-9]+", |lex| with_arity(lex.slice()))]
     AssignIterative(String),
 
+    #[regex("[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*\\^-1", |lex| {
+        let slice = lex.slice();
+        String::from(&slice[..slice.len() - 3])
+    })]
+    Inverse(String),
+
     #[regex("\\$[0-9]+", |lex| {
         let mut parse = lex.slice().split('$');
         parse.next();
@@ -39,7 +61,12 @@ pub enum Token {
     Argument(usize),
 
     #[regex("\"([^\"\\\\]|\\\\n|\\\\r|\\\\t|\\\\\\\\|\\\\\"|\\\\[0-9a-fA-F][0-9a-fA-F])*\"", |lex| from_string(lex.slice()))]
-    #[regex("[\\-\\+]?[0-9]+(/[0-9]+)?", |lex| lex.slice().parse())]
+    // Digits can be separated by single underscores, which are dropped before parsing
+    // underscores anywhere else are matched too, so that the literal becomes unrecognized
+    #[regex("[\\-\\+]?_*[0-9][0-9_]*(\\.[0-9_]+)?(/[0-9_]+(\\.[0-9_]+)?)?", |lex| without_separators(lex.slice(), 10).and_then(|literal| from_decimal(&literal)))]
+    // Hexadecimal and binary literals can have a sign and a denominator too, like hex prints them
+    #[regex("[\\-\\+]?0x[0-9a-fA-F_]+(/0x[0-9a-fA-F_]+)?", |lex| without_separators(lex.slice(), 16).and_then(|literal| from_radix(&literal, 16)))]
+    #[regex("[\\-\\+]?0b[01_]+(/0b[01_]+)?", |lex| without_separators(lex.slice(), 2).and_then(|literal| from_radix(&literal, 2)))]
     Number(Rational),
 
     #[regex("-")]
@@ -66,9 +93,135 @@ pub enum Token {
     #[regex("_")]
     ExpMod,
 
+    #[regex("ieeerem")]
+    IeeeRemainder,
+
+    #[regex("gcd")]
+    Gcd,
+
+    #[regex("lcm")]
+    Lcm,
+
+    #[regex("ishyp")]
+    Hypotenuse,
+
+    #[regex("eq")]
+    Equal,
+
+    #[regex("ne")]
+    NotEqual,
+
+    #[regex("lt")]
+    Less,
+
+    #[regex("le")]
+    LessEqual,
+
+    #[regex("gt")]
+    Greater,
+
+    #[regex("ge")]
+    GreaterEqual,
+
+    #[regex("nCr")]
+    Binomial,
+
+    #[regex("nPr")]
+    Permutations,
+
+    #[regex("modinv")]
+    ModInverse,
+
+    #[regex("band")]
+    BitAnd,
+
+    #[regex("bor")]
+    BitOr,
+
+    #[regex("bxor")]
+    BitXor,
+
+    #[regex("shl")]
+    ShiftLeft,
+
+    #[regex("shr")]
+    ShiftRight,
+
+    #[regex("tetr")]
+    Tetration,
+
+    #[regex("absdiff")]
+    AbsDiff,
+
+    #[regex("ilog")]
+    IntegerLog,
+
+    #[regex("and")]
+    And,
+
+    #[regex("or")]
+    Or,
+
+    #[regex("period")]
+    Period,
+
+    #[regex("abs")]
+    Abs,
+
+    #[regex("neg")]
+    Negate,
+
+    #[regex("fact")]
+    Factorial,
+
+    #[regex("isprime")]
+    Prime,
+
+    #[regex("sign")]
+    Sign,
+
+    #[regex("floor")]
+    Floor,
+
+    #[regex("ceil")]
+    Ceil,
+
+    #[regex("round")]
+    Round,
+
+    #[regex("numer")]
+    Numerator,
+
+    #[regex("denom")]
+    Denominator,
+
+    #[regex("digits")]
+    Digits,
+
+    #[regex("digitsum")]
+    DigitSum,
+
+    #[regex("recip")]
+    Reciprocal,
+
+    #[regex("not")]
+    Not,
+
+    #[regex("collatz")]
+    Collatz,
+
+    #[regex("fib")]
+    Fibonacci,
+
     #[regex("\\?")]
     If,
 
+    #[regex("clamp")]
+    Clamp,
+
+    #[regex("between")]
+    Between,
+
     #[regex("=")]
     Return,
 
@@ -93,11 +246,137 @@ pub enum Token {
     #[regex("&")]
     Format,
 
+    #[regex("decode")]
+    Decode,
+
     #[regex("\\[\\]")]
     Approx,
 
+    #[regex("\\[[0-9]+\\]", |lex| {
+        let slice = lex.slice();
+        slice[1..slice.len() - 1].parse()
+    })]
+    Decimal(usize),
+
+    #[regex("weightedsum")]
+    WeightedSum,
+
+    #[regex("sum")]
+    Sum,
+
+    #[regex("avg")]
+    Average,
+
+    #[regex("steps")]
+    Steps,
+
+    #[regex("trackminmax")]
+    TrackMinMax,
+
+    #[regex("minmax")]
+    MinMax,
+
+    #[regex("resetminmax")]
+    ResetMinMax,
+
+    #[regex("hex")]
+    Hex,
+
+    #[regex("stackreport")]
+    StackReport,
+
+    #[regex("memo")]
+    Memoize,
+
+    #[regex("maxdepth")]
+    MaxDepth,
+
+    #[regex("maxsteps")]
+    MaxSteps,
+
+    #[regex("maxbits")]
+    MaxBits,
+
+    #[regex("clearbut")]
+    ClearBut,
+
+    #[regex("pick")]
+    Pick,
+
+    #[regex("roll")]
+    Roll,
+
+    #[regex("select")]
+    Select,
+
+    #[regex("divmod")]
+    DivMod,
+
+    #[regex("repeat")]
+    Repeat,
+
+    #[regex("tree")]
+    Tree,
+
+    #[regex("trace")]
+    Trace,
+
+    #[regex("depth")]
+    Depth,
+
+    #[regex("mixed")]
+    Mixed,
+
+    #[regex("parts")]
+    Parts,
+
+    #[regex("group")]
+    Group,
+
+    #[regex("decimals")]
+    Decimals,
+
+    #[regex("fractions")]
+    Fractions,
+
+    #[regex("cfrac")]
+    ContinuedFraction,
+
+    #[regex("time")]
+    Time,
+
+    #[regex("profile")]
+    Profile,
+
+    #[regex("help")]
+    Help,
+
+    #[regex("version")]
+    Version,
+
+    #[regex("reload")]
+    Reload,
+
+    #[regex("dump")]
+    Dump,
+
+    #[regex(r"save:[^ \t\n\f\r]+", |lex| String::from(&lex.slice()[5..]))]
+    Save(String),
+
+    #[regex(r"output:[^ \t\n\f\r]+", |lex| String::from(&lex.slice()[7..]))]
+    Output(String),
+
+    #[regex("check:[a-zA-Z]([a-zA-Z0-9]|-[a-zA-Z0-9]|_[a-zA-Z0-9])*[\\|@][0-9]+", |lex| with_arity(&lex.slice()[6..]))]
+    Check(String),
+
+    #[regex("close")]
+    Close,
+
+    // Line comments start with ';', block comments go from ';{' to the first ';}'
+    // an unterminated block comment is an error
     #[error]
-    #[regex(";.*", logos::skip)]
+    #[regex(r";([^\{\n].*)?", logos::skip)]
+    #[regex(r";\{", block_comment)]
     #[regex(r"[ \t\n\f\r]+", logos::skip)]
     Error,
 }
@@ -121,22 +400,258 @@ impl fmt::Display for Token {
             Divide => write!(f, "/"),
             IntegerDiv => write!(f, "\\"),
             If => write!(f, "?"),
+            Clamp => write!(f, "clamp"),
+            Between => write!(f, "between"),
             PositiveMinus => write!(f, "~"),
             Exp => write!(f, "^"),
             ExpMod => write!(f, "_"),
+            IeeeRemainder => write!(f, "ieeerem"),
+            Gcd => write!(f, "gcd"),
+            Lcm => write!(f, "lcm"),
+            Hypotenuse => write!(f, "ishyp"),
+            Equal => write!(f, "eq"),
+            NotEqual => write!(f, "ne"),
+            Less => write!(f, "lt"),
+            LessEqual => write!(f, "le"),
+            Greater => write!(f, "gt"),
+            GreaterEqual => write!(f, "ge"),
+            Binomial => write!(f, "nCr"),
+            Permutations => write!(f, "nPr"),
+            ModInverse => write!(f, "modinv"),
+            BitAnd => write!(f, "band"),
+            BitOr => write!(f, "bor"),
+            BitXor => write!(f, "bxor"),
+            ShiftLeft => write!(f, "shl"),
+            ShiftRight => write!(f, "shr"),
+            Tetration => write!(f, "tetr"),
+            AbsDiff => write!(f, "absdiff"),
+            IntegerLog => write!(f, "ilog"),
+            And => write!(f, "and"),
+            Or => write!(f, "or"),
+            Period => write!(f, "period"),
+            Abs => write!(f, "abs"),
+            Negate => write!(f, "neg"),
+            Factorial => write!(f, "fact"),
+            Prime => write!(f, "isprime"),
+            Sign => write!(f, "sign"),
+            Floor => write!(f, "floor"),
+            Ceil => write!(f, "ceil"),
+            Round => write!(f, "round"),
+            Numerator => write!(f, "numer"),
+            Denominator => write!(f, "denom"),
+            Digits => write!(f, "digits"),
+            DigitSum => write!(f, "digitsum"),
+            Reciprocal => write!(f, "recip"),
+            Not => write!(f, "not"),
+            Collatz => write!(f, "collatz"),
+            Fibonacci => write!(f, "fib"),
+            Return => write!(f, "="),
+            Partial => write!(f, "#"),
+            Print => write!(f, ":"),
+            Flush => write!(f, ">"),
+            Duplicate => write!(f, "<"),
+            Drop => write!(f, "!"),
+            Empty => write!(f, "%"),
+            Format => write!(f, "&"),
+            Decode => write!(f, "decode"),
+            Approx => write!(f, "[]"),
+            Decimal(digits) => write!(f, "[{}]", digits),
+            WeightedSum => write!(f, "weightedsum"),
+            Sum => write!(f, "sum"),
+            Average => write!(f, "avg"),
+            Steps => write!(f, "steps"),
+            TrackMinMax => write!(f, "trackminmax"),
+            MinMax => write!(f, "minmax"),
+            ResetMinMax => write!(f, "resetminmax"),
+            Hex => write!(f, "hex"),
+            StackReport => write!(f, "stackreport"),
+            Memoize => write!(f, "memo"),
+            MaxDepth => write!(f, "maxdepth"),
+            MaxSteps => write!(f, "maxsteps"),
+            MaxBits => write!(f, "maxbits"),
+            ClearBut => write!(f, "clearbut"),
+            Pick => write!(f, "pick"),
+            Roll => write!(f, "roll"),
+            Select => write!(f, "select"),
+            DivMod => write!(f, "divmod"),
+            Repeat => write!(f, "repeat"),
+            Tree => write!(f, "tree"),
+            Trace => write!(f, "trace"),
+            Depth => write!(f, "depth"),
+            Mixed => write!(f, "mixed"),
+            Parts => write!(f, "parts"),
+            Group => write!(f, "group"),
+            Decimals => write!(f, "decimals"),
+            Fractions => write!(f, "fractions"),
+            ContinuedFraction => write!(f, "cfrac"),
+            Time => write!(f, "time"),
+            Profile => write!(f, "profile"),
+            Help => write!(f, "help"),
+            Version => write!(f, "version"),
+            Reload => write!(f, "reload"),
+            Dump => write!(f, "dump"),
+            Close => write!(f, "close"),
+            Save(path) => write!(f, "save:{}", path),
+            Output(path) => write!(f, "output:{}", path),
+            Check(header) => write!(f, "check:{}", header),
             Argument(index) => write!(f, "${}", index),
             Identifier(name) => write!(f, "{}", name),
-            _ => write!(f, "Unprintable"),
+            // These keep the '=', '-', '|n' and '@n' of the source
+            AssignVariable(name)
+            | Undefine(name)
+            | AssignFunction(name)
+            | AssignIterative(name) => write!(f, "{}", name),
+            KeepVariable(name) => write!(f, ":={}", name),
+            Inverse(name) => write!(f, "{}^-1", name),
+            // Errors never get past the lexer
+            Error => write!(f, "Unprintable"),
         }
     }
 }
 
+// Keep a function header only if its arity can be parsed
+#[inline]
+fn with_arity(header: &str) -> Option<String> {
+    header
+        .rsplit(&['|', '@'][..])
+        .next()?
+        .parse::<usize>()
+        .ok()?;
+    Some(String::from(header))
+}
+
+// The name of the operators that replaced functions of the std_lib
+// user definitions with the same name hide them, so that old scripts keep working
+#[inline]
+fn keyword_name(token: &Token) -> Option<String> {
+    match token {
+        Abs | Floor | Factorial => Some(token.to_string()),
+        _ => None,
+    }
+}
+
+// Print the tokens of source back, separated by single spaces
+// comments and blank lines are dropped, tokens keep the line they started on
+// unrecognized tokens are reported and left as they were
+pub fn normalize(source: &str) -> String {
+    let mut normalized = String::with_capacity(source.len());
+    let mut last = 0;
+    for (token, span) in Token::lexer(source).spanned() {
+        if !normalized.is_empty() {
+            normalized.push(if source[last..span.start].contains('\n') {
+                '\n'
+            } else {
+                ' '
+            });
+        }
+        if let Error = token {
+            eprintln!(
+                "Error at {}..{}: unrecognized '{}', left as is",
+                span.start,
+                span.end,
+                &source[span.clone()]
+            );
+            normalized.push_str(&source[span.clone()]);
+        } else {
+            normalized.push_str(&token.to_string());
+        }
+        last = span.end;
+    }
+    normalized
+}
+
+// Format a number as a normalized fraction (or integer)
+#[inline]
+fn fraction(num: Rational) -> String {
+    let (num, den) = num.into_parts();
+    if den.is_one() {
+        format!("{}", num)
+    } else {
+        format!("{}/{}", num, den)
+    }
+}
+
+// Format a number as an integer part followed by a proper fraction
+// the sign goes on the integer part (-7/2 is -3 1/2)
+#[inline]
+fn mixed(num: Rational) -> String {
+    let (num, den) = num.into_parts();
+    // divmod truncates, so the integer part goes toward zero
+    let (int, rem) = num.divmod(&den);
+    if rem.is_zero() {
+        format!("{}", int)
+    } else if int.is_zero() {
+        format!("{}/{}", rem, den)
+    } else {
+        format!("{} {}/{}", int, rem.abs(), den)
+    }
+}
+
+// Format an integer in hexadecimal, the same way it's read
+#[inline]
+fn hexadecimal(num: Int) -> String {
+    if num < 0 {
+        format!("-0x{}", (-num).to_str_radix(16, false))
+    } else {
+        format!("0x{}", num.to_str_radix(16, false))
+    }
+}
+
+// Insert an underscore every three digits, counting from the right of each number
+// (12345/1000 becomes 12_345/1_000)
+fn group_digits(text: &str) -> String {
+    let mut grouped = String::with_capacity(text.len() * 4 / 3);
+    let mut digits = text.chars().rev().peekable();
+    let mut count = 0;
+    while let Some(c) = digits.next() {
+        grouped.push(c);
+        if c.is_ascii_digit() {
+            count += 1;
+            if count % 3 == 0 && matches!(digits.peek(), Some(next) if next.is_ascii_digit()) {
+                grouped.push('_');
+            }
+        } else {
+            count = 0;
+        }
+    }
+    grouped.chars().rev().collect()
+}
+
 // Structure for keeping the current state of the calculator
 pub struct Calculator {
     stack: Vec<Token>,
     table: HashMap<String, Object>,
+    context: Context,
+    // Becomes false when something in the current line was incomplete
+    complete: bool,
+    // Smallest and biggest numbers pushed while tracking is on
+    track_min_max: bool,
+    min_max: Option<(Rational, Rational)>,
+    // Print the number of elements in stack after each line
+    report_stack: bool,
+    // Execution trees already parsed, indexed by their expression
+    trees: HashMap<String, ExecTree>,
+    // Separate the digits of the results in groups of three
+    group_digits: bool,
+    // Where the results get written, stdout unless redirected to a file
+    output: Box<dyn Write>,
+    // How the results get printed
+    mode: DisplayMode,
+    // Source of the std_lib, and the file it comes from (unless it's the embedded one)
+    std_lib: Option<(String, Option<PathBuf>)>,
+}
+
+// Results can be printed as exact fractions,
+// or as decimals with a fixed number of digits
+#[derive(Clone, Copy)]
+enum DisplayMode {
+    Fraction,
+    Decimal(usize),
 }
 
+// Maximum number of execution trees kept in cache
+const TREE_CACHE_SIZE: usize = 64;
+
 impl Calculator {
     // Empty calculator
     #[inline]
@@ -144,33 +659,353 @@ impl Calculator {
         Calculator {
             stack: Vec::new(),
             table: HashMap::new(),
+            context: Context::new(),
+            complete: true,
+            track_min_max: false,
+            min_max: None,
+            report_stack: true,
+            trees: HashMap::new(),
+            group_digits: false,
+            output: Box::new(stdout()),
+            mode: DisplayMode::Fraction,
+            std_lib: None,
         }
     }
 
+    // Print a result
+    #[inline]
+    fn print_result(&mut self, num: Rational) {
+        let line = match self.mode {
+            DisplayMode::Fraction => format!("> {}", self.grouped(fraction(num))),
+            // Only the integer part gets grouped
+            DisplayMode::Decimal(digits) => {
+                let text = short_decimal(num, digits);
+                match text.split_once('.') {
+                    Some((int, frac)) => format!("> {}.{}", self.grouped(int.to_string()), frac),
+                    None => format!("> {}", self.grouped(text.to_string())),
+                }
+            }
+        };
+        self.output(line);
+    }
+
+    // Write a line to the output
+    #[inline]
+    fn output(&mut self, line: String) {
+        writeln!(self.output, "{}", line).unwrap_or_else(|_| eprintln!("Unable to write output"));
+    }
+
+    // Group the digits of a formatted number, if grouping is on
+    #[inline]
+    fn grouped(&self, text: String) -> String {
+        if self.group_digits {
+            group_digits(&text)
+        } else {
+            text
+        }
+    }
+
+    // Writes every definition as rpn-l, one per line
+    // a definition comes after the ones it uses, because their arity is needed for parsing it
+    // Definitions that are the same in base are skipped
+    pub fn save_table(&self, base: Option<&HashMap<String, Object>>) -> String {
+        let (unchanged, mut pending): (Vec<String>, Vec<String>) = self
+            .names()
+            .into_iter()
+            .partition(|name| base.and_then(|base| base.get(name)) == Some(&self.table[name]));
+        let mut saved: HashSet<String> = unchanged.into_iter().collect();
+        let mut lines = Vec::new();
+
+        while !pending.is_empty() {
+            let (ready, waiting): (Vec<String>, Vec<String>) =
+                pending.into_iter().partition(|name| {
+                    self.table[name].identifiers().into_iter().all(|used| {
+                        used == name || saved.contains(used) || !self.table.contains_key(used)
+                    })
+                });
+
+            // Definitions that use each other can't be ordered, save them anyway
+            let ready = if ready.is_empty() {
+                waiting.clone()
+            } else {
+                ready
+            };
+            for name in &ready {
+                lines.push(self.table[name].to_rpn(name));
+            }
+            pending = waiting
+                .into_iter()
+                .filter(|name| !ready.contains(name))
+                .collect();
+            saved.extend(ready);
+        }
+
+        lines.join("\n")
+    }
+
+    // Parses saved definitions, one line at a time
+    // Returns the number of lines that were skipped because they didn't define anything
+    pub fn restore_table(&mut self, saved: &str) -> usize {
+        let report_stack = self.report_stack;
+        self.report_stack = false;
+
+        let mut skipped = 0;
+        for line in saved.lines() {
+            // A valid definition leaves nothing in stack
+            if !self.parse(String::from(line)) || !self.stack.is_empty() {
+                skipped += 1;
+                self.stack.clear();
+            }
+        }
+
+        self.report_stack = report_stack;
+        skipped
+    }
+
+    // Writes a script that recreates the whole session, the definitions first, then the stack
+    fn save(&self, path: &str) {
+        let stack: Vec<String> = self.stack.iter().map(|token| token.to_string()).collect();
+        let script = format!("{}\n{}\n", self.save_table(None), stack.join(" "));
+
+        match std::fs::write(path, script) {
+            Ok(_) => println!("Saved to {}", path),
+            Err(err) => eprintln!("Unable to write {}: {}", path, err),
+        }
+    }
+
+    // Copy of all the variables and functions, to compare them with later ones
+    pub fn snapshot(&self) -> HashMap<String, Object> {
+        self.table.clone()
+    }
+
+    // Names of all the variables and functions, in alphabetical order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.table.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Arguments are only allowed in function declarations,
+    // so if the stack has any, a declaration was started and not finished yet
+    pub fn in_declaration(&self) -> bool {
+        self.stack.iter().any(|token| matches!(token, Argument(_)))
+    }
+
+    #[inline]
+    pub fn set_report_stack(&mut self, report_stack: bool) {
+        self.report_stack = report_stack;
+    }
+
+    // Parse the std_lib, and remember where it comes from for reload
+    pub fn load_std_lib(&mut self, source: String, path: Option<PathBuf>) {
+        self.parse(source.clone());
+        self.std_lib = Some((source, path));
+    }
+
+    // Parse the std_lib again, from its file if it has one
+    // definitions with the same name get replaced, the others are kept
+    fn reload(&mut self) {
+        let (source, path) = match self.std_lib.take() {
+            Some(std_lib) => std_lib,
+            None => {
+                eprintln!("No std_lib to reload");
+                return;
+            }
+        };
+
+        let source = match &path {
+            Some(file) => match read_to_string(file) {
+                Ok(source) => source,
+                Err(err) => {
+                    eprintln!("Unable to read std_lib {}: {}", file.display(), err);
+                    self.std_lib = Some((source, path));
+                    return;
+                }
+            },
+            None => source,
+        };
+
+        // It's in the middle of a line, the reports and the state of the line are left to it
+        let report_stack = self.report_stack;
+        let track_min_max = self.track_min_max;
+        let complete = self.complete;
+        let interrupted = interrupted();
+        self.report_stack = false;
+        self.track_min_max = false;
+
+        let before = self.table.clone();
+        self.parse(source.clone());
+        let loaded = self
+            .table
+            .iter()
+            .filter(|(name, object)| before.get(*name) != Some(object))
+            .count();
+
+        self.report_stack = report_stack;
+        self.track_min_max = track_min_max;
+        self.complete = complete;
+        INTERRUPTED.store(interrupted, Ordering::Relaxed);
+        println!("Reloaded std_lib, {} definitions changed", loaded);
+
+        self.std_lib = Some((source, path));
+    }
+
     // To be called from main,
     // Parse a line into tokens and compute them
+    // Returns false if any expression in the line was incomplete
     #[inline]
-    pub fn parse(&mut self, word: String) {
-        for token in Token::lexer(&word) {
-            self.analyze(token);
+    pub fn parse(&mut self, word: String) -> bool {
+        self.complete = true;
+        // Ctrl-C stops the rest of the line it was pressed in, not the following ones
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        let mut tokens = Token::lexer(&word).spanned().peekable();
+        let mut after_value = false;
+        let mut empty = true;
+        while let Some((token, span)) = tokens.next() {
+            empty = false;
+            let token = self.shadow(token);
+
+            // "5 -3" is 5 and the number -3, not a subtraction, which is confusing
+            // warn about it if nothing after the number could make sense of it
+            let slice = &word[span.clone()];
+            if let (Number(_), true) = (&token, after_value) {
+                if slice.starts_with('-')
+                    && matches!(
+                        tokens.peek(),
+                        None | Some((Return, _)) | Some((Partial, _)) | Some((Flush, _))
+                    )
+                {
+                    eprintln!(
+                        "Warning at {}..{}: '{}' is a negative number, to subtract write '{} -'",
+                        span.start,
+                        span.end,
+                        slice,
+                        &slice[1..]
+                    );
+                }
+            }
+            after_value = matches!(token, Number(_) | Identifier(_) | Argument(_));
+
+            if let Error = token {
+                // Errors are reported here, where their position is known
+                eprintln!(
+                    "Error at {}..{}: unrecognized '{}', dropped",
+                    span.start, span.end, slice
+                );
+            } else {
+                self.analyze(token);
+            }
+        }
+
+        // Cached values only live for one line
+        self.context.clear();
+
+        // Blank lines and comments leave everything as it was, there's nothing to report
+        if empty {
+            return self.complete;
         }
 
         // Inform the user of the number of elements still in stack
-        println!("{} elements in stack", self.stack.len());
+        if self.report_stack {
+            self.output(format!("{} elements in stack", self.stack.len()));
+        }
+        if self.track_min_max {
+            self.print_min_max();
+        }
+
+        self.complete
+    }
+
+    // A keyword with the name of a definition is read as that definition,
+    // so that definitions made before the keyword existed keep working
+    #[inline]
+    fn shadow(&self, token: Token) -> Token {
+        match keyword_name(&token) {
+            Some(name) if self.table.contains_key(&name) => Identifier(name),
+            _ => token,
+        }
+    }
+
+    // Inside a declaration, a keyword with the name of the function is a recursive call,
+    // the function will hide the keyword as soon as it's defined
+    fn shadow_recursion(&mut self, function_name: &str, index: usize) {
+        for token in &mut self.stack[index..] {
+            if keyword_name(token).as_deref() == Some(function_name) {
+                *token = Identifier(String::from(function_name));
+            }
+        }
+    }
+
+    #[inline]
+    fn print_min_max(&mut self) {
+        let line = if let Some((min, max)) = &self.min_max {
+            format!(
+                "min {} max {}",
+                fraction(min.clone()),
+                fraction(max.clone())
+            )
+        } else {
+            String::from("No numbers tracked")
+        };
+        self.output(line);
+    }
+
+    // Undo the fake function of a declaration that failed
+    #[inline]
+    fn put_back(&mut self, name: String, old: Option<Object>) {
+        match old {
+            Some(object) => self.table.insert(name, object),
+            None => self.table.remove(&name),
+        };
+    }
+
+    // Report an incomplete expression or declaration
+    // Report the token that misses arguments, when the top of the stack is incomplete
+    // The whole stack is parsed, because an incomplete expression goes down to the bottom
+    fn explain_incomplete(&self) {
+        if let Err(err) = parse_tree(self.stack.clone(), &self.table) {
+            eprintln!("{}", err);
+        }
+    }
+
+    #[inline]
+    fn incomplete(&mut self, message: &str) {
+        eprintln!("{}", message);
+        self.complete = false;
     }
 
     // Find the index of the stack at which the function declaration ends
     #[inline]
-    fn extract_function(
-        &mut self,
+    fn extract_function(&self, function_name: &String, arity: usize, index: usize) -> Found {
+        let (to_copy, index) = self.scan_function(function_name, arity, index);
+
+        // If it managed to complete the expression with what was
+        // found in stack, then index contains where to split
+        if to_copy == 0 {
+            FoundAt(index)
+        } else {
+            NotFound
+        }
+    }
+
+    // Walk the stack down from index, until an expression of the function is complete
+    // Returns the number of operands still missing (0 if complete) and where the walk stopped
+    fn scan_function(
+        &self,
         function_name: &String,
         arity: usize,
         mut index: usize,
-    ) -> Found {
+    ) -> (usize, usize) {
         let mut to_copy = 1;
 
         while to_copy > 0 && index > 0 {
             match &self.stack[index - 1] {
+                // A keyword with the same name is a self reference too
+                token if keyword_name(token).as_ref() == Some(function_name) => {
+                    to_copy += arity;
+                    to_copy -= 1;
+                }
+
                 Identifier(name) => {
                     // Check for self reference (for recursion)
                     if name.eq(function_name) {
@@ -190,45 +1025,175 @@ impl Calculator {
 
                 Number(_) | Argument(_) => to_copy -= 1,
 
-                Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp => to_copy += 1,
+                // Unary operators replace their argument
+                Period | Abs | Negate | Factorial | Prime | Sign | Floor | Ceil | Round
+                | Numerator | Denominator | Digits | DigitSum | Reciprocal | Not | Collatz
+                | Fibonacci => {}
+
+                Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp
+                | IeeeRemainder | Gcd | Lcm | Hypotenuse | Equal | NotEqual | Less | LessEqual
+                | Greater | GreaterEqual | Binomial | Permutations | BitAnd | BitOr | BitXor
+                | ShiftLeft | ShiftRight | ModInverse | Tetration | AbsDiff | IntegerLog | And
+                | Or => to_copy += 1,
 
-                If | ExpMod => to_copy += 2,
+                If | ExpMod | Clamp | Between => to_copy += 2,
 
-                _ => panic!("Corrupted stack"),
+                // Gives up, as if the stack ended here
+                token => {
+                    eprintln!(
+                        "Corrupted stack, '{}' can't be part of an expression",
+                        token
+                    );
+                    return (to_copy, index);
+                }
             }
 
             // Moves index
             index -= 1;
         }
 
-        // If it managed to complete the expression with what was
-        // found in stack, then index contains where to split
-        if to_copy == 0 {
-            FoundAt(index)
-        } else {
-            NotFound
+        (to_copy, index)
+    }
+
+    // Report how much of a function declaration is on the stack, without declaring it
+    fn check(&mut self, header: &str) {
+        let (function_name, arity, expected) = match header.split_once('|') {
+            Some((name, arity)) => (name, arity.parse().unwrap(), 1),
+            None => {
+                let (name, arity) = header.split_once('@').unwrap();
+                let arity: usize = arity.parse().unwrap();
+                (name, arity, arity + 2)
+            }
+        };
+        let function_name = String::from(function_name);
+
+        let mut index = self.stack.len();
+        for found in 0..expected {
+            let (missing, stop) = self.scan_function(&function_name, arity, index);
+            if missing > 0 {
+                self.output(format!(
+                    "{}: found {} of {} expressions, the next one misses {} operands (gave up at index {})",
+                    header, found, expected, missing, stop
+                ));
+                return;
+            }
+            index = stop;
         }
+        let line = format!(
+            "{}: complete, the declaration starts at index {} of {}",
+            header,
+            index,
+            self.stack.len()
+        );
+        self.output(line);
     }
 
     // Receive a token and decide what to do
     fn analyze(&mut self, token: Token) {
         match token {
-            // Drop all errors
-            Error => eprintln!("Dropped unrecognized token!"),
+            // Errors never get here, parse already reported and dropped them
+            Error => {}
 
             // Compute and print top of the stack
             Return => {
+                if let Some(num) = self.compute() {
+                    self.print_result(num);
+                } else {
+                    // Print error if arguments are missing
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Compute and print top of the stack as a mixed number
+            Mixed => {
+                if let Some(num) = self.compute() {
+                    self.output(format!("> {}", mixed(num)));
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Compute and print numerator and denominator of the top of the stack
+            Parts => {
                 if let Some(mut num) = self.compute() {
                     num.normalize();
                     let (num, den) = num.into_parts();
-                    if den.is_one() {
-                        println!("> {}", num);
+                    self.output(format!("> {} {}", num, den));
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Compute and print top of the stack, and the time it took
+            // parsing is not timed, only the computation
+            Time => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression");
+                } else if let Some(key) = self.cache_tree(expression) {
+                    let start = Instant::now();
+                    let result =
+                        self.trees[&key].reduce(&self.table, &Vec::new(), &mut self.context);
+                    let elapsed = start.elapsed();
+
+                    if let Some(num) = result {
+                        self.print_result(num);
+                        println!("Computed in {:?}", elapsed);
                     } else {
-                        println!("> {}/{}", num, den);
+                        self.incomplete("Incomplete expression");
                     }
                 } else {
-                    // Print error if arguments are missing
-                    eprintln!("Incomplete expression");
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Compute and print top of the stack, and the number of nodes reduced
+            Profile => {
+                self.context.steps = 0;
+                if let Some(num) = self.compute() {
+                    self.print_result(num);
+                    println!("Computed in {} steps", self.context.steps);
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Print every operator and the assignment syntaxes
+            Help => {
+                println!("{:<12} {:<20} {:<6} description", "symbol", "name", "arity");
+                for (symbol, name, arity, description) in OPERATORS {
+                    println!("{:<12} {:<20} {:<6} {}", symbol, name, arity, description);
+                }
+                println!();
+                for (syntax, description) in ASSIGNMENTS {
+                    println!("{:<36} {}", syntax, description);
+                }
+            }
+
+            Version => println!("rpn-c {}", env!("CARGO_PKG_VERSION")),
+
+            Reload => self.reload(),
+
+            // Compute and print the continued fraction of the top of the stack
+            ContinuedFraction => {
+                if let Some(num) = self.compute() {
+                    let coefficients: Vec<String> = continued_fraction(num)
+                        .into_iter()
+                        .map(|coefficient| coefficient.to_string())
+                        .collect();
+                    if coefficients.len() == 1 {
+                        self.output(format!("> [{}]", coefficients[0]));
+                    } else {
+                        self.output(format!(
+                            "> [{}; {}]",
+                            coefficients[0],
+                            coefficients[1..].join(", ")
+                        ));
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
                 }
             }
 
@@ -238,91 +1203,277 @@ impl Calculator {
                 if let Some(mut num) = self.compute() {
                     num.normalize();
                     let (num, den) = num.into_parts();
-                    // Turns the numerator into a vector of bytes and writes them to stdout
+                    // Turns the numerator into a vector of bytes and writes them to the output
                     // In case of error it just prints a message
                     // The resulting string will be inverted, this makes it easier to build it
-                    std::io::stdout()
+                    self.output
                         .write(&(Stringer::from(num).collect::<Vec<u8>>())[..])
                         .unwrap_or_else(|_| {
                             eprintln!("Cannot print numerator string");
                             0
                         });
-                    println!("");
+                    self.output(String::new());
 
                     // If the denominator is *not* one it does the same, on a new line
                     // Be carefull with non-coprimes, because they get normalized
                     if !den.is_one() {
-                        std::io::stdout()
+                        self.output
                             .write(&(Stringer::from(den).collect::<Vec<u8>>())[..])
                             .unwrap_or_else(|_| {
                                 eprintln!("Cannot print numerator string");
                                 0
                             });
-                        println!("");
+                        self.output(String::new());
                     }
                 } else {
                     // Print error if arguments are missing
-                    eprintln!("Incomplete expression");
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Computes the top of the stack and prints it as a string literal
+            Decode => {
+                if let Some(num) = self.compute() {
+                    let (num, den) = num.into_parts();
+                    if num < 0 || !den.is_one() {
+                        eprintln!("Only natural numbers can be written as strings");
+                    } else {
+                        self.output(format!("> {}", to_literal(num)));
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Computes the top of the stack and prints it in hexadecimal
+            Hex => {
+                if let Some(num) = self.compute() {
+                    let (num, den) = num.into_parts();
+                    if den.is_one() {
+                        self.output(format!("> {}", hexadecimal(num)));
+                    } else {
+                        self.output(format!("> {}/{}", hexadecimal(num), hexadecimal(den)));
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
                 }
             }
 
             // Computes the top of the stack and prints an approximation
             Approx => {
                 if let Some(num) = self.compute() {
-                    println!("> {:e}", num.to_f64());
+                    match to_f64(num) {
+                        Ok(value) => self.output(format!("> {:e}", value)),
+                        Err(message) => eprintln!("{}", message),
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Computes the top of the stack and prints it with a fixed number of decimal digits
+            Decimal(digits) => {
+                if let Some(num) = self.compute() {
+                    self.output(format!("> {}", to_decimal(num, digits)));
                 } else {
-                    eprintln!("Incomplete expression");
+                    self.incomplete("Incomplete expression");
                 }
             }
 
             // Compute and print top of the stack
             // Put result back in stack
             Partial => {
-                if let Some(mut num) = self.compute() {
-                    println!("< {}", num);
-                    num.normalize();
-                    self.stack.push(Number(num));
+                if let Some(num) = self.compute() {
+                    let line = format!("< {}", self.grouped(num.to_string()));
+                    self.output(line);
+                    self.push_number(num);
                 } else {
                     // Print error if arguments are missing
-                    eprintln!("Incomplete expression");
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Print each step of the computation of the top of the stack
+            // then put the expression back
+            Steps => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression");
+                } else {
+                    // The whole expression counts as a single computation for maxsteps
+                    self.context.hold();
+                    let derived = parse_tree(expression.clone(), &self.table).map(|tree| {
+                        tree.derive(
+                            &self.table,
+                            &mut self.context,
+                            &mut Vec::new(),
+                            &mut *self.output,
+                        )
+                    });
+                    self.context.release();
+                    match derived {
+                        Ok(Some(_)) => {}
+                        Ok(None) => self.incomplete("Incomplete expression"),
+                        Err(err) => self.incomplete(&err),
+                    }
+                    self.stack.extend(expression);
+                }
+            }
+
+            // Compute and print the top of the stack, writing every operation
+            Trace => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression");
+                } else {
+                    match parse_tree(expression, &self.table) {
+                        Ok(tree) => {
+                            // The whole expression counts as a single computation for maxsteps
+                            self.context.hold();
+                            let result =
+                                tree.trace(&self.table, &mut self.context, &mut *self.output);
+                            self.context.release();
+                            if let Some(num) = result {
+                                self.print_result(num);
+                            } else {
+                                self.incomplete("Incomplete expression");
+                            }
+                        }
+                        Err(err) => self.incomplete(&err),
+                    }
+                }
+            }
+
+            // Print the execution tree of the top of the stack
+            // then put the expression back
+            Tree => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression");
+                } else {
+                    match parse_tree(expression.clone(), &self.table) {
+                        Ok(tree) => tree
+                            .print(0, &mut *self.output)
+                            .unwrap_or_else(|_| eprintln!("Unable to write output")),
+                        Err(err) => self.incomplete(&err),
+                    }
+                    self.stack.extend(expression);
                 }
             }
 
             // Compute top of stack and duplicate it
+            // An incomplete expression is left on the stack as it is
             Duplicate => {
-                if let Some(mut num) = self.compute() {
-                    self.stack.push(Number(num.clone()));
-                    num.normalize();
-                    self.stack.push(Number(num));
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression, stack left untouched");
+                } else if let Some(num) = self.evaluate(expression) {
+                    self.push_number(num.clone());
+                    self.push_number(num);
                 } else {
-                    eprintln!("Incomplete expression, dropped stack");
+                    self.incomplete("Unable to compute expression, dropped");
                 }
             }
 
             // Compute and print entire stack
             Flush => {
-                for result in self.compute_all() {
-                    if let Some(mut num) = result {
-                        num.normalize();
-                        let (num, den) = num.into_parts();
-                        if den.is_one() {
-                            println!("> {}", num);
-                        } else {
-                            println!("> {}/{}", num, den);
-                        }
+                let results = self.compute_all();
+                let count = results.len();
+
+                for (position, result) in results.into_iter().enumerate() {
+                    if let Some(num) = result {
+                        self.print_result(num);
                     } else {
                         // Print error if arguments are missing
-                        eprintln!("Incomplete expression");
+                        // expressions are counted from the top, as they are printed
+                        self.incomplete(&format!(
+                            "Expression {} of {} (from the top) is incomplete",
+                            position + 1,
+                            count
+                        ));
                     }
                 }
             }
 
+            // Compute the entire stack as (value, weight) pairs
+            // and print the sum of their products
+            WeightedSum => {
+                // compute_all empties the stack, it's put back if the sum can't be computed
+                let stack = self.stack.clone();
+                // compute_all starts from the top, pairs are read from the bottom
+                let results: Option<Vec<Rational>> = self.compute_all().into_iter().rev().collect();
+
+                if let Some(results) = results {
+                    if results.len() % 2 == 0 {
+                        self.print_result(
+                            results
+                                .chunks(2)
+                                .fold(Rational::zero(), |sum, pair| sum + &pair[0] * &pair[1]),
+                        );
+                    } else {
+                        self.stack = stack;
+                        eprintln!(
+                            "Weighted sum needs an even number of values, stack left untouched"
+                        );
+                    }
+                } else {
+                    self.stack = stack;
+                    self.incomplete("Incomplete expression, stack left untouched");
+                }
+            }
+
+            // Compute the entire stack and print the sum, or the average, of the values
+            Sum | Average => {
+                // compute_all empties the stack, it's put back if the sum can't be computed
+                let stack = self.stack.clone();
+                let results: Option<Vec<Rational>> = self.compute_all().into_iter().collect();
+
+                if let Some(results) = results {
+                    let count = results.len();
+                    let sum = results
+                        .into_iter()
+                        .fold(Rational::zero(), |sum, num| sum + num);
+
+                    if let Sum = token {
+                        self.print_result(sum);
+                    } else if count > 0 {
+                        self.print_result(sum / Rational::from(count));
+                    } else {
+                        eprintln!("Average needs at least one value");
+                    }
+                } else {
+                    self.stack = stack;
+                    self.incomplete("Incomplete expression, stack left untouched");
+                }
+            }
+
             // Print all elements in stack without computing
             Print => {
-                for token in &self.stack {
-                    print!("{} ", token);
+                let line = self
+                    .stack
+                    .iter()
+                    .map(|token| format!("{} ", token))
+                    .collect();
+                self.output(line);
+            }
+
+            // Push the number of complete expressions in stack
+            // they get clipped from a copy, so the stack is left untouched
+            Depth => {
+                let mut stack = self.stack.clone();
+                let mut depth = 0;
+                while !clip_head(&mut stack, &self.table).is_empty() {
+                    depth += 1;
                 }
-                println!("");
+                self.stack.push(Number(Rational::from(depth)));
             }
 
             // Flush all stack without computing it
@@ -330,16 +1481,233 @@ impl Calculator {
                 self.stack.clear();
             }
 
+            // Flush all stack except the top expression, without computing it
+            ClearBut => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression, stack left untouched");
+                } else {
+                    self.stack = expression;
+                }
+            }
+
+            // Copy the n-th expression from the top of the stack (1 is the top) on top of it
+            // n must be a number, not an expression
+            Pick => match self.stack.pop() {
+                Some(Number(n)) => {
+                    let count = floor_abs(n.clone(), "Count", "pick");
+                    match self.top_expressions(&count) {
+                        Some((_, mut expressions)) if !expressions.is_empty() => {
+                            self.stack.append(expressions.last_mut().unwrap());
+                        }
+                        _ => {
+                            // Put the count back, so the stack is left as it was
+                            self.stack.push(Number(n));
+                            self.incomplete("Not enough expressions, stack left untouched");
+                        }
+                    }
+                }
+                token => {
+                    self.stack.extend(token);
+                    self.incomplete("Missing count, stack left untouched");
+                }
+            },
+
+            // Move the n-th expression from the top of the stack (1 is the top) on top of it
+            // the ones above it go down by one
+            Roll => match self.stack.pop() {
+                Some(Number(n)) => {
+                    let count = floor_abs(n.clone(), "Count", "roll");
+                    match self.top_expressions(&count) {
+                        Some((rest, mut expressions)) if !expressions.is_empty() => {
+                            let deepest = expressions.pop().unwrap();
+                            self.stack = rest;
+                            for expression in expressions.into_iter().rev() {
+                                self.stack.extend(expression);
+                            }
+                            self.stack.extend(deepest);
+                        }
+                        _ => {
+                            // Put the count back, so the stack is left as it was
+                            self.stack.push(Number(n));
+                            self.incomplete("Not enough expressions, stack left untouched");
+                        }
+                    }
+                }
+                token => {
+                    self.stack.extend(token);
+                    self.incomplete("Missing count, stack left untouched");
+                }
+            },
+
+            // Replace n expressions and an index with the expression at that index (0 is the deepest)
+            // The index is evaluated, the chosen expression isn't
+            Select => match self.stack.pop() {
+                Some(Number(n)) => {
+                    let count = floor_abs(n.clone(), "Count", "select");
+                    if count.is_zero() {
+                        self.stack.push(Number(n));
+                        eprintln!("Count must be at least 1");
+                        self.incomplete("Stack left untouched");
+                    } else {
+                        match self.top_expressions(&(&count + 1)) {
+                            Some((rest, mut expressions)) => {
+                                // The stack is only changed once the index is known to be valid
+                                let index = self.evaluate(expressions.remove(0)).map(floor);
+                                match index {
+                                    Some(index) if index >= 0 && index < count => {
+                                        self.stack = rest;
+                                        // The expressions go from the top down
+                                        let position = usize::from(&(&count - 1 - index));
+                                        self.stack.append(&mut expressions[position]);
+                                    }
+                                    Some(index) => {
+                                        self.stack.push(Number(n));
+                                        eprintln!(
+                                            "Index {} out of range, 0 to {}",
+                                            index,
+                                            count - 1
+                                        );
+                                        self.incomplete("Stack left untouched");
+                                    }
+                                    None => {
+                                        self.stack.push(Number(n));
+                                        self.incomplete(
+                                            "Unable to compute index, stack left untouched",
+                                        );
+                                    }
+                                }
+                            }
+                            None => {
+                                self.stack.push(Number(n));
+                                self.incomplete("Not enough expressions, stack left untouched");
+                            }
+                        }
+                    }
+                }
+                token => {
+                    self.stack.extend(token);
+                    self.incomplete("Missing count, stack left untouched");
+                }
+            },
+
+            // Replace the top two expressions with their floored quotient and the remainder
+            // It's a command, because expressions only have one result
+            DivMod => match self.top_expressions(&Int::from(2)) {
+                Some((rest, mut expressions)) => {
+                    self.stack = rest;
+                    let a = self.evaluate(expressions.pop().unwrap());
+                    let b = self.evaluate(expressions.pop().unwrap());
+
+                    match (a, b) {
+                        (Some(_), Some(b)) if b.is_zero() => {
+                            self.incomplete("Cannot divide by zero, dropped")
+                        }
+                        (Some(a), Some(b)) => {
+                            let q = Rational::from(floor(a.clone() / &b));
+                            let r = a - &b * &q;
+                            self.push_number(q);
+                            self.push_number(r);
+                        }
+                        _ => self.incomplete("Unable to compute expressions, dropped"),
+                    }
+                }
+                None => self.incomplete("Incomplete expression, stack left untouched"),
+            },
+
+            // Replace x and n with f applied n times to x, f must be a unary function
+            // It's a command, because f only takes one expression
+            Repeat => match self.stack.pop() {
+                Some(Identifier(name))
+                    if matches!(
+                        self.table.get(&name),
+                        Some(Function(1, _)) | Some(Iterative(1, _, _, _))
+                    ) =>
+                {
+                    match self.top_expressions(&Int::from(2)) {
+                        Some((rest, mut expressions)) => {
+                            self.stack = rest;
+                            let x = self.evaluate(expressions.pop().unwrap());
+                            let n = self.evaluate(expressions.pop().unwrap());
+
+                            if let (Some(x), Some(n)) = (x, n) {
+                                let count = floor_abs(n, "Count", "repeat");
+                                if let Some(result) = self.repeat(name, x, &count) {
+                                    self.push_number(result);
+                                } else {
+                                    self.incomplete("Unable to compute expression, dropped");
+                                }
+                            } else {
+                                self.incomplete("Unable to compute expressions, dropped");
+                            }
+                        }
+                        None => {
+                            self.stack.push(Identifier(name));
+                            self.incomplete("Incomplete expression, stack left untouched");
+                        }
+                    }
+                }
+                Some(Identifier(name)) => {
+                    eprintln!("{} is not a function of arity 1", name);
+                    self.stack.push(Identifier(name));
+                    self.incomplete("Stack left untouched");
+                }
+                token => {
+                    self.stack.extend(token);
+                    self.incomplete("Missing function, stack left untouched");
+                }
+            },
+
             // Assign value to global variable
             // Drops previous value
+            // An incomplete expression is left on the stack as it is
             AssignVariable(mut name) => {
-                if let Some(val) = self.compute() {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    // Print error if arguments are missing
+                    self.incomplete("Incomplete expression, stack left untouched");
+                } else if let Some(val) = self.evaluate(expression) {
                     // Remove '=' from the name before inserting it
                     name.remove(0);
                     self.table.insert(name, Variable(val));
+                    // Cached trees and calls might depend on the old value
+                    self.invalidate();
                 } else {
-                    // Print error if arguments are missing
-                    eprintln!("Incomplete expression, dropped stack");
+                    self.incomplete("Unable to compute expression, dropped");
+                }
+            }
+
+            // Assign value to global variable, and put it back in stack
+            KeepVariable(name) => {
+                let expression = clip_head(&mut self.stack, &self.table);
+
+                if expression.is_empty() {
+                    self.explain_incomplete();
+                    self.incomplete("Incomplete expression, stack left untouched");
+                } else if let Some(val) = self.evaluate(expression) {
+                    self.push_number(val.clone());
+                    self.table.insert(name, Variable(val));
+                    // Cached trees and calls might depend on the old value
+                    self.invalidate();
+                } else {
+                    self.incomplete("Unable to compute expression, dropped");
+                }
+            }
+
+            // Remove a variable or a function from the table
+            // functions that refer to it will fail to compute
+            Undefine(mut name) => {
+                // Remove '-' from the name before looking for it
+                name.remove(0);
+                if self.table.remove(&name).is_some() {
+                    self.invalidate();
+                    self.output(format!("Removed {}", name));
+                } else {
+                    eprintln!("No such definition");
                 }
             }
 
@@ -352,8 +1720,14 @@ impl Calculator {
                 let arity = parse.next().unwrap().parse().unwrap();
 
                 if let FoundAt(index) = self.extract_function(&function_name, arity, index) {
+                    // Kept as it was, in case the declaration can't be parsed
+                    let declaration = self.stack[index..].to_vec();
+                    self.shadow_recursion(&function_name, index);
+                    // Cached trees and calls might depend on the old definition
+                    self.invalidate();
                     // Insert a fake function for parsing recursive functions
-                    self.table.insert(
+                    // keep the previous object, in case
+                    let old = self.table.insert(
                         function_name.clone(),
                         Object::Function(
                             arity,
@@ -364,12 +1738,19 @@ impl Calculator {
                         ),
                     );
                     // insert real function
-                    self.table.insert(
-                        function_name,
-                        Function(arity, parse_tree(self.stack.split_off(index), &self.table)),
-                    );
+                    match parse_tree(self.stack.split_off(index), &self.table) {
+                        Ok(tree) => {
+                            self.table.insert(function_name, Function(arity, tree));
+                        }
+                        Err(err) => {
+                            // Nothing gets declared, the old object and the stack are put back
+                            self.put_back(function_name, old);
+                            self.stack.extend(declaration);
+                            self.incomplete(&err);
+                        }
+                    }
                 } else {
-                    eprintln!("Incomplete function declaration");
+                    self.incomplete("Incomplete function declaration");
                 }
             }
 
@@ -392,51 +1773,84 @@ impl Calculator {
                         index = split_index;
                     } else {
                         found = false;
-                        eprintln!("Incomplete function declaration");
+                        self.incomplete("Incomplete function declaration");
                     }
                     expressions -= 1;
                 }
 
-                // Insert a fake function for parsing recursive functions
-                // keep the previous object, in case
-                let old = self.table.insert(
-                    function_name.clone(),
-                    Object::Function(
-                        arity,
-                        ExecTree {
-                            token: Number(Rational::zero()),
-                            arguments: Vec::new(),
-                        },
-                    ),
-                );
                 // If arity is correct
                 if arity + 2 == indices.len() {
+                    // Kept as it was, in case the declaration can't be parsed
+                    let declaration = self.stack[index..].to_vec();
+                    self.shadow_recursion(&function_name, index);
+                    // Cached trees and calls might depend on the old definition
+                    self.invalidate();
+                    // Insert a fake function for parsing recursive functions
+                    // keep the previous object, in case
+                    let old = self.table.insert(
+                        function_name.clone(),
+                        Object::Function(
+                            arity,
+                            ExecTree {
+                                token: Number(Rational::zero()),
+                                arguments: Vec::new(),
+                            },
+                        ),
+                    );
                     let mut expressions = Vec::new();
 
                     for index in indices {
                         expressions.push(self.stack.split_off(index));
                     }
 
-                    let mut expressions: Vec<ExecTree> = expressions
+                    let expressions: Result<Vec<ExecTree>, String> = expressions
                         .into_iter()
                         .map(|exp| parse_tree(exp, &self.table))
                         .rev()
                         .collect();
-                    let condition = expressions.remove(arity + 1);
-                    let last = expressions.remove(arity);
-                    // Insert real function
-                    self.table.insert(
-                        function_name,
-                        Iterative(arity, expressions, last, condition),
-                    );
-                } else {
-                    // If arity is incorrect, put the old object back
-                    if let Some(object) = old {
-                        self.table.insert(function_name, object);
+                    match expressions {
+                        Ok(mut expressions) => {
+                            let condition = expressions.remove(arity + 1);
+                            let last = expressions.remove(arity);
+                            // Insert real function
+                            self.table.insert(
+                                function_name,
+                                Iterative(arity, expressions, last, condition),
+                            );
+                        }
+                        Err(err) => {
+                            // Nothing gets declared, the old object and the stack are put back
+                            self.put_back(function_name, old);
+                            self.stack.extend(declaration);
+                            self.incomplete(&err);
+                        }
                     }
                 }
             }
 
+            Inverse(name) => self.invert(&name),
+
+            Check(header) => self.check(&header),
+
+            Save(path) => self.save(&path),
+
+            // Print every definition as rpn-l, ready to be loaded as a script
+            Dump => {
+                let table = self.save_table(None);
+                if !table.is_empty() {
+                    self.output(table);
+                }
+            }
+
+            // Write the results to a file (truncating it) instead of stdout
+            Output(path) => match File::create(&path) {
+                Ok(file) => self.output = Box::new(file),
+                Err(err) => eprintln!("Unable to write {}: {}", path, err),
+            },
+
+            // Write the results to stdout again
+            Close => self.output = Box::new(stdout()),
+
             // Eliminate top of stack without computing it
             Drop => {
                 let mut to_drop = 1;
@@ -454,21 +1868,149 @@ impl Calculator {
 
                         Some(Number(_)) | Some(Argument(_)) => to_drop -= 1,
 
+                        Some(Period) | Some(Abs) | Some(Negate) | Some(Factorial) | Some(Prime)
+                        | Some(Sign) | Some(Floor) | Some(Ceil) | Some(Round) | Some(Numerator)
+                        | Some(Denominator) | Some(Digits) | Some(DigitSum) | Some(Reciprocal)
+                        | Some(Not) | Some(Collatz) | Some(Fibonacci) => {}
+
                         Some(Plus) | Some(Minus) | Some(Times) | Some(Divide)
-                        | Some(PositiveMinus) | Some(IntegerDiv) | Some(Exp) => to_drop += 1,
+                        | Some(PositiveMinus) | Some(IntegerDiv) | Some(Exp)
+                        | Some(IeeeRemainder) | Some(Gcd) | Some(Lcm) | Some(Hypotenuse)
+                        | Some(Equal) | Some(NotEqual) | Some(Less) | Some(LessEqual)
+                        | Some(Greater) | Some(GreaterEqual) | Some(Binomial)
+                        | Some(Permutations) | Some(BitAnd) | Some(BitOr) | Some(BitXor)
+                        | Some(ShiftLeft) | Some(ShiftRight) | Some(ModInverse)
+                        | Some(Tetration) | Some(AbsDiff) | Some(IntegerLog) | Some(And)
+                        | Some(Or) => to_drop += 1,
+
+                        Some(If) | Some(ExpMod) | Some(Clamp) | Some(Between) => to_drop += 2,
+
+                        // Put it back and stop dropping
+                        Some(token) => {
+                            eprintln!(
+                                "Corrupted stack, '{}' can't be part of an expression",
+                                token
+                            );
+                            self.stack.push(token);
+                            to_drop = 0;
+                        }
+                    }
+                }
+            }
+
+            // Start or stop tracking the pushed numbers
+            TrackMinMax => {
+                self.track_min_max = !self.track_min_max;
+            }
+
+            MinMax => self.print_min_max(),
 
-                        Some(If) | Some(ExpMod) => to_drop += 2,
+            // Start or stop printing the number of elements in stack
+            StackReport => {
+                self.report_stack = !self.report_stack;
+            }
 
-                        _ => panic!("Corrupted stack"),
+            ResetMinMax => {
+                self.min_max = None;
+            }
+
+            // Start or stop grouping the digits of the results
+            Group => {
+                self.group_digits = !self.group_digits;
+            }
+
+            // Print the results as decimals, with a fixed number of digits
+            Decimals => {
+                if let Some(num) = self.compute() {
+                    let digits = floor_abs(num, "Digits", "decimals");
+                    // Every result is scaled by 10^digits, which takes more than 3 bits per digit
+                    match digits.to_string().parse::<usize>() {
+                        Ok(digits) if digits <= self.context.max_bits / 10 * 3 => {
+                            self.mode = DisplayMode::Decimal(digits)
+                        }
+                        _ => eprintln!("Digits are too many"),
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Print the results as exact fractions again
+            Fractions => {
+                self.mode = DisplayMode::Fraction;
+            }
+
+            // Start or stop remembering the results of function calls
+            Memoize => {
+                self.context.memoize = !self.context.memoize;
+            }
+
+            // Set the maximum number of nested reductions
+            MaxDepth => {
+                if let Some(num) = self.compute() {
+                    let depth = floor_abs(num, "Depth", "maxdepth");
+                    match depth.to_string().parse() {
+                        Ok(depth) if depth <= MAX_DEPTH => self.context.max_depth = depth,
+                        _ => eprintln!("Depth is too big, the limit is {}", MAX_DEPTH),
                     }
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Set the maximum number of operations of a single computation
+            MaxSteps => {
+                if let Some(num) = self.compute() {
+                    let steps = floor_abs(num, "Steps", "maxsteps");
+                    if let Ok(steps) = steps.to_string().parse() {
+                        self.context.max_steps = steps;
+                    } else {
+                        eprintln!("Steps are too many");
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
+                }
+            }
+
+            // Set the maximum size of the results of tetration
+            MaxBits => {
+                if let Some(num) = self.compute() {
+                    let bits = floor_abs(num, "Bits", "maxbits");
+                    if let Ok(bits) = bits.to_string().parse() {
+                        self.context.max_bits = bits;
+                    } else {
+                        eprintln!("Bits are too many");
+                    }
+                } else {
+                    self.incomplete("Incomplete expression");
                 }
             }
 
             // Push numbers and variables in stack
+            Number(value) => {
+                if self.track_min_max {
+                    self.min_max = Some(match self.min_max.take() {
+                        Some((min, max)) => (
+                            if value < min { value.clone() } else { min },
+                            if value > max { value.clone() } else { max },
+                        ),
+                        None => (value.clone(), value.clone()),
+                    });
+                }
+                self.push_number(value);
+            }
             _ => self.stack.push(token),
         }
     }
 
+    // Push a number on the stack in lowest terms
+    // so the stack always holds reduced fractions, whatever produced them
+    #[inline]
+    fn push_number(&mut self, mut value: Rational) {
+        value.normalize();
+        self.stack.push(Number(value));
+    }
+
     // Compute top of stack and returns it
     // Returns None if the stack empties in advance
     #[inline]
@@ -477,20 +2019,204 @@ impl Calculator {
         let expression = clip_head(&mut self.stack, &self.table);
 
         // Return none if the expression was incomplete
-        if expression.len() == 0 {
+        if expression.is_empty() {
+            self.explain_incomplete();
             return None;
         }
 
-        // Parse execution tree from expression
-        let tree = parse_tree(expression, &self.table);
+        self.evaluate(expression)
+    }
+
+    // Apply the unary function name to value, count times
+    // The call node is built once, only its argument changes
+    fn repeat(&mut self, name: String, mut value: Rational, count: &Int) -> Option<Rational> {
+        let mut call = ExecTree {
+            token: Identifier(name),
+            arguments: vec![ExecTree {
+                token: Number(Rational::zero()),
+                arguments: Vec::new(),
+            }],
+        };
+        let mut i = Int::zero();
+        while &i < count {
+            // Each call is a short computation, that would not check for interruptions
+            if interrupted() {
+                eprintln!("Computation interrupted");
+                return None;
+            }
+            call.arguments[0].token = Number(value);
+            value = call.reduce(&self.table, &Vec::new(), &mut self.context)?;
+            i += 1;
+        }
+        Some(value)
+    }
+
+    // Split the top count expressions of the stack, without touching it
+    // Returns what's left under them, and the expressions from the top one down,
+    // or None if the stack doesn't hold that many complete expressions
+    fn top_expressions(&self, count: &Int) -> Option<(Vec<Token>, Vec<Vec<Token>>)> {
+        let mut stack = self.stack.clone();
+        let mut expressions = Vec::new();
+        let mut found = Int::zero();
+        while found < *count {
+            let expression = clip_head(&mut stack, &self.table);
+            if expression.is_empty() {
+                return None;
+            }
+            expressions.push(expression);
+            found += 1;
+        }
+        Some((stack, expressions))
+    }
+
+    // Compute an expression already removed from the stack
+    fn evaluate(&mut self, expression: Vec<Token>) -> Option<Rational> {
+        // Parse execution tree from expression, or reuse the cached one
+        let key = self.cache_tree(expression)?;
 
         // Calculate value for exevution tree
-        tree.reduce(&self.table, &Vec::new())
+        self.trees[&key].reduce(&self.table, &Vec::new(), &mut self.context)
+    }
+
+    // Parse an expression, unless the same expression was already parsed
+    // Returns the key of the tree in the cache, or None if it can't be parsed
+    fn cache_tree(&mut self, expression: Vec<Token>) -> Option<String> {
+        // Debug output tells apart an operator and a function with the same name
+        let key = expression
+            .iter()
+            .map(|token| format!("{:?}", token))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if !self.trees.contains_key(&key) {
+            // Keep the cache small, start over when it's full
+            if self.trees.len() >= TREE_CACHE_SIZE {
+                self.trees.clear();
+            }
+            match parse_tree(expression, &self.table) {
+                Ok(tree) => {
+                    self.trees.insert(key.clone(), tree);
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return None;
+                }
+            }
+        }
+
+        Some(key)
+    }
+
+    // Forget everything that depends on the table
+    // to be called whenever a definition changes
+    #[inline]
+    fn invalidate(&mut self) {
+        self.trees.clear();
+        self.context.clear();
+    }
+
+    // Calls a unary function on a single value
+    #[inline]
+    fn call(&mut self, name: &str, x: Rational) -> Option<Rational> {
+        ExecTree {
+            token: Identifier(String::from(name)),
+            arguments: vec![ExecTree {
+                token: Number(x),
+                arguments: Vec::new(),
+            }],
+        }
+        .reduce(&self.table, &Vec::new(), &mut self.context)
+    }
+
+    // Finds x such that f(x) = target, with a bisection between lo and hi
+    // Prints x as soon as the range gets smaller than tolerance
+    // The arguments are only dropped once they are all known to be valid
+    fn invert(&mut self, name: &str) {
+        match self.table.get(name) {
+            Some(Function(1, _)) | Some(Iterative(1, _, _, _)) => {}
+            _ => {
+                eprintln!("{} is not a function of arity 1", name);
+                return;
+            }
+        }
+
+        // Arguments are split from the top, tolerance first
+        let (rest, expressions) = match self.top_expressions(&Int::from(4)) {
+            Some(split) => split,
+            None => {
+                self.explain_incomplete();
+                self.incomplete("Incomplete expression, stack left untouched");
+                return;
+            }
+        };
+        let mut values = Vec::new();
+        for expression in expressions {
+            match self.evaluate(expression) {
+                Some(value) => values.push(value),
+                None => {
+                    self.incomplete("Unable to compute expression, stack left untouched");
+                    return;
+                }
+            }
+        }
+        let (target, mut lo, mut hi) = (
+            values.pop().unwrap(),
+            values.pop().unwrap(),
+            values.pop().unwrap(),
+        );
+        let tolerance = values.pop().unwrap();
+
+        if tolerance <= Rational::zero() {
+            self.incomplete("Tolerance must be positive, stack left untouched");
+            return;
+        }
+        if lo > hi {
+            self.incomplete("Lo is greater than hi, stack left untouched");
+            return;
+        }
+
+        // The function must be monotonic, so f(x) - target must change sign between the endpoints
+        // that is, the target must be between f(lo) and f(hi)
+        let (f_lo, f_hi) = match (self.call(name, lo.clone()), self.call(name, hi.clone())) {
+            (Some(f_lo), Some(f_hi)) => (f_lo, f_hi),
+            _ => {
+                self.incomplete("Unable to compute the endpoints, stack left untouched");
+                return;
+            }
+        };
+        if (target < f_lo && target < f_hi) || (target > f_lo && target > f_hi) {
+            eprintln!("The target is not between {}(lo) and {}(hi)", name, name);
+            self.incomplete("Stack left untouched");
+            return;
+        }
+        let increasing = f_lo <= f_hi;
+        self.stack = rest;
+
+        // Halve the range until it's small enough
+        while hi.clone() - &lo > tolerance {
+            if interrupted() {
+                self.incomplete("Computation interrupted");
+                return;
+            }
+            let mid = (lo.clone() + &hi) / Rational::from(2);
+            if let Some(f_mid) = self.call(name, mid.clone()) {
+                if (f_mid < target) == increasing {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            } else {
+                self.incomplete("Unable to compute expression, dropped");
+                return;
+            }
+        }
+
+        self.print_result((lo + hi) / Rational::from(2));
     }
 
     #[inline]
     fn compute_all(&mut self) -> Vec<Option<Rational>> {
-        let mut all_trees = Vec::new();
+        let mut all_results = Vec::new();
 
         let mut found_incomplete = false;
 
@@ -498,26 +2224,160 @@ impl Calculator {
             let expression = clip_head(&mut self.stack, &self.table);
 
             if expression.len() > 0 {
-                // Parse execution tree from expression
-                let tree = parse_tree(expression, &self.table);
-
-                // Calculate value for exevution tree
-                all_trees.push(Some(tree));
+                all_results.push(self.evaluate(expression));
             } else {
                 found_incomplete = true;
-                all_trees.push(None);
+                all_results.push(None);
             }
         }
 
-        all_trees
-            .into_iter()
-            .map(|tree| {
-                if let Some(tree) = tree {
-                    tree.reduce(&self.table, &Vec::new())
-                } else {
-                    None
-                }
-            })
-            .collect()
+        all_results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One of each variant, except Error that never gets past the lexer
+    fn samples() -> Vec<Token> {
+        let text = |text: &str| String::from(text);
+        vec![
+            Identifier(text("abc")),
+            AssignVariable(text("=x")),
+            KeepVariable(text("x")),
+            Undefine(text("-x_1")),
+            AssignFunction(text("f|2")),
+            AssignIterative(text("f@2")),
+            Inverse(text("sq")),
+            Argument(3),
+            Number(Rational::from(5)),
+            Number(Rational::from(-5)),
+            Number(Rational::new(Int::from(-7), Int::from(2))),
+            Decimal(5),
+            Save(text("session.rpnl")),
+            Output(text("/tmp/results.txt")),
+            Check(text("f@1")),
+            Minus,
+            Plus,
+            Times,
+            Divide,
+            PositiveMinus,
+            IntegerDiv,
+            Exp,
+            ExpMod,
+            IeeeRemainder,
+            Gcd,
+            Lcm,
+            Hypotenuse,
+            Equal,
+            NotEqual,
+            Less,
+            LessEqual,
+            Greater,
+            GreaterEqual,
+            Binomial,
+            Permutations,
+            ModInverse,
+            BitAnd,
+            BitOr,
+            BitXor,
+            ShiftLeft,
+            ShiftRight,
+            Tetration,
+            AbsDiff,
+            IntegerLog,
+            And,
+            Or,
+            Period,
+            Abs,
+            Negate,
+            Factorial,
+            Prime,
+            Sign,
+            Floor,
+            Ceil,
+            Round,
+            Numerator,
+            Denominator,
+            Digits,
+            DigitSum,
+            Reciprocal,
+            Not,
+            Collatz,
+            Fibonacci,
+            If,
+            Clamp,
+            Between,
+            Return,
+            Partial,
+            Print,
+            Flush,
+            Duplicate,
+            Drop,
+            Empty,
+            Format,
+            Decode,
+            Approx,
+            WeightedSum,
+            Sum,
+            Average,
+            Steps,
+            TrackMinMax,
+            MinMax,
+            ResetMinMax,
+            Hex,
+            StackReport,
+            Memoize,
+            MaxDepth,
+            MaxSteps,
+            MaxBits,
+            ClearBut,
+            Pick,
+            Roll,
+            Select,
+            DivMod,
+            Repeat,
+            Tree,
+            Trace,
+            Depth,
+            Mixed,
+            Parts,
+            Group,
+            Decimals,
+            Fractions,
+            ContinuedFraction,
+            Time,
+            Profile,
+            Help,
+            Version,
+            Reload,
+            Dump,
+            Close,
+        ]
+    }
+
+    #[test]
+    fn every_token_prints_as_itself() {
+        for token in samples() {
+            let printed = token.to_string();
+            let lexed: Vec<Token> = Token::lexer(&printed).collect();
+            assert_eq!(lexed, vec![token], "{} lexes differently", printed);
+        }
+    }
+
+    #[test]
+    fn declaration_spans_lines() {
+        let mut calculator = Calculator::new();
+        calculator.set_report_stack(false);
+        assert!(!calculator.in_declaration());
+        calculator.parse(String::from("$0 2"));
+        assert!(calculator.in_declaration());
+        calculator.parse(String::from("* dbl|1"));
+        assert!(!calculator.in_declaration());
+        assert!(calculator.names().contains(&String::from("dbl")));
+        // Dropping the arguments ends the declaration too
+        calculator.parse(String::from("$0 %"));
+        assert!(!calculator.in_declaration());
     }
 }
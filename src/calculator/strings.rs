@@ -131,6 +131,32 @@ pub fn from_string(string: &str) -> Rational {
     Rational::from(num)
 }
 
+// Inverse of from_string, writes the bytes of num as a string literal
+// Bytes without a shortcut escape that aren't printable are written as two hex digits
+pub fn to_literal(num: Int) -> String {
+    let mut bytes: Vec<u8> = Stringer::from(num).collect();
+    // Stringer pads the last bytes with zeroes
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    let mut literal = String::with_capacity(bytes.len() + 2);
+    literal.push('"');
+    for byte in bytes {
+        match byte {
+            10 => literal.push_str("\\n"),
+            13 => literal.push_str("\\r"),
+            9 => literal.push_str("\\t"),
+            92 => literal.push_str("\\\\"),
+            34 => literal.push_str("\\\""),
+            32..=126 => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
 #[inline]
 fn from_hex(hex: u8) -> u8 {
     if hex >= 48 && hex <= 57 {
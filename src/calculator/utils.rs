@@ -1,17 +1,37 @@
-use super::execution::Object;
+use super::execution::{Object, INTERRUPTED};
 use super::Token;
+use logos::{Filter, Lexer};
 use num_traits::{One, Zero};
 use ramp::rational::Rational;
 use ramp::Int;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use Object::*;
 use Token::*;
 
+// Checked by the loops that can run for long, so that Ctrl-C stops them too
+#[inline]
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
 pub enum Found {
     NotFound,
     FoundAt(usize),
 }
 
+// Skips everything up to the first ';}'
+// blocks don't nest, without a ';}' the ';{' is an error
+pub fn block_comment(lex: &mut Lexer<Token>) -> Filter<()> {
+    if let Some(end) = lex.remainder().find(";}") {
+        lex.bump(end + 2);
+        Filter::Skip
+    } else {
+        Filter::Emit(())
+    }
+}
+
 #[inline]
 pub fn clip_head(stack: &mut Vec<Token>, table: &HashMap<String, Object>) -> Vec<Token> {
     let mut to_copy = 1;
@@ -38,11 +58,25 @@ pub fn clip_head(stack: &mut Vec<Token>, table: &HashMap<String, Object>) -> Vec
                 i = 1;
             }
 
-            Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp => to_copy += 1,
+            // Unary operators replace their argument
+            Period | Abs | Negate | Factorial | Prime | Sign | Floor | Ceil | Round | Numerator
+            | Denominator | Digits | DigitSum | Reciprocal | Not | Collatz | Fibonacci => {}
+
+            Plus | Minus | Times | Divide | PositiveMinus | IntegerDiv | Exp | IeeeRemainder
+            | Gcd | Lcm | Hypotenuse | Equal | NotEqual | Less | LessEqual | Greater
+            | GreaterEqual | Binomial | Permutations | BitAnd | BitOr | BitXor | ShiftLeft
+            | ShiftRight | ModInverse | Tetration | AbsDiff | IntegerLog | And | Or => to_copy += 1,
 
-            If | ExpMod => to_copy += 2,
+            If | ExpMod | Clamp | Between => to_copy += 2,
 
-            _ => panic!("Corrupted stack"),
+            // Gives up, like for arguments
+            token => {
+                eprintln!(
+                    "Corrupted stack, '{}' can't be part of an expression",
+                    token
+                );
+                i = 1;
+            }
         }
 
         // Moves index
@@ -70,3 +104,654 @@ pub fn floor_abs(x: Rational, role: &'static str, position: &'static str) -> Int
 
     (num / den).abs()
 }
+
+// Biggest integer lesser or equal than x
+#[inline]
+pub fn floor(x: Rational) -> Int {
+    let (num, den) = x.into_parts();
+    // divmod truncates, negative numbers need to go one step further down
+    let (q, r) = num.divmod(&den);
+    if r < 0 {
+        q - 1
+    } else {
+        q
+    }
+}
+
+// Smallest integer greater or equal than x
+#[inline]
+pub fn ceil(x: Rational) -> Int {
+    let (num, den) = x.into_parts();
+    // divmod truncates, positive numbers need to go one step further up
+    let (q, r) = num.divmod(&den);
+    if r > 0 {
+        q + 1
+    } else {
+        q
+    }
+}
+
+// Coefficients of the continued fraction of x
+// the first one is the floor of x, the others are all positive
+pub fn continued_fraction(x: Rational) -> Vec<Int> {
+    let (mut num, mut den) = x.into_parts();
+    let mut coefficients = Vec::new();
+    while !den.is_zero() {
+        // Euclidean step, with a floored quotient (for negative numbers)
+        let (mut q, mut r) = num.divmod(&den);
+        if r < 0 {
+            q -= 1;
+            r += &den;
+        }
+        coefficients.push(q);
+        num = den;
+        den = r;
+    }
+    coefficients
+}
+
+#[inline]
+pub fn round_half_even(x: Rational) -> Int {
+    let (num, den) = x.into_parts();
+    // divmod truncates, move the quotient down so that the remainder is positive
+    let (mut q, mut r) = num.divmod(&den);
+    if r < 0 {
+        q -= 1;
+        r += &den;
+    }
+
+    // Compare twice the remainder with the denominator to find the nearest integer
+    // on ties, pick the even one
+    let r = r * 2;
+    if r > den || (r == den && !q.is_even()) {
+        q += 1;
+    }
+    q
+}
+
+// Euclidean algorithm, gcd(a, 0) = a
+#[inline]
+pub fn gcd(mut a: Int, mut b: Int) -> Int {
+    while !b.is_zero() {
+        let r = a % &b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+// Product of all the integers from 1 to n
+// Returns None if interrupted
+pub fn factorial(n: &Int) -> Option<Int> {
+    let mut result = Int::one();
+    let mut i = Int::from(2);
+    while &i <= n {
+        if interrupted() {
+            return None;
+        }
+        result *= &i;
+        i += 1;
+    }
+    Some(result)
+}
+
+// Number of steps of the Collatz map needed to bring n to 1
+// n must be positive, returns None if interrupted
+pub fn collatz(mut n: Int) -> Option<usize> {
+    let mut steps = 0;
+    while !n.is_one() {
+        if interrupted() {
+            return None;
+        }
+        if n.is_even() {
+            n >>= 1;
+        } else {
+            n = n * 3 + 1;
+        }
+        steps += 1;
+    }
+    Some(steps)
+}
+
+// n-th Fibonacci number, by fast doubling:
+// F(2k) = F(k) * (2F(k+1) - F(k)) and F(2k+1) = F(k)^2 + F(k+1)^2
+// returns None if the result would exceed max_bits
+pub fn fibonacci(n: &Int, max_bits: usize) -> Option<Int> {
+    // F(n) has about 0.694*n bits
+    if n * 7 > Int::from(max_bits) * 10 {
+        return None;
+    }
+
+    // (a, b) = (F(k), F(k+1)), k takes the bits of n from the most significant
+    let mut a = Int::zero();
+    let mut b = Int::one();
+    for bit in (0..n.bit_length()).rev() {
+        let double = &a * (&b * 2 - &a);
+        b = &a * &a + &b * &b;
+        a = double;
+        if n.bit(bit) {
+            b += &a;
+            a = &b - &a;
+        }
+    }
+    Some(a)
+}
+
+// Number of ordered choices of k elements out of n
+// multiplies n-k+1..=n instead of dividing two factorials
+// Returns None if interrupted
+pub fn permutations(n: &Int, k: &Int) -> Option<Int> {
+    if k > n {
+        return Some(Int::zero());
+    }
+    let mut result = Int::one();
+    let mut i = n - k + 1;
+    while &i <= n {
+        if interrupted() {
+            return None;
+        }
+        result *= &i;
+        i += 1;
+    }
+    Some(result)
+}
+
+// Number of unordered choices of k elements out of n
+// every partial product i..=n-k+i is divisible by i!, so the division is exact
+// Returns None if interrupted
+pub fn binomial(n: &Int, k: &Int) -> Option<Int> {
+    if k > n {
+        return Some(Int::zero());
+    }
+    // Choosing k is the same as leaving out n-k, use the shortest loop
+    let k = std::cmp::min(k.clone(), n - k);
+    let mut result = Int::one();
+    let mut i = Int::one();
+    while i <= k {
+        if interrupted() {
+            return None;
+        }
+        result = result * (n - &k + &i) / &i;
+        i += 1;
+    }
+    Some(result)
+}
+
+// Miller-Rabin primality test
+// with these witnesses the test is deterministic for n < 3.3*10^24,
+// above that a composite passing it is extremely unlikely
+// Returns None if interrupted
+pub fn is_prime(n: &Int) -> Option<bool> {
+    const WITNESSES: [usize; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+    // 0 and 1 are not prime, small primes are witnesses themselves
+    if *n < 2 {
+        return Some(false);
+    }
+    for witness in WITNESSES.iter() {
+        let witness = Int::from(*witness);
+        if *n == witness {
+            return Some(true);
+        }
+        if (n % &witness).is_zero() {
+            return Some(false);
+        }
+    }
+
+    // Write n-1 as d*2^s with d odd
+    let n_minus_one: Int = n - 1;
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while d.is_even() {
+        d >>= 1;
+        s += 1;
+    }
+
+    'witness: for witness in WITNESSES.iter() {
+        let mut x = Int::from(*witness).pow_mod(&d, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            if interrupted() {
+                return None;
+            }
+            x = x.square() % n;
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return Some(false);
+    }
+    Some(true)
+}
+
+// Inverse of a in modulo m (with m > 0), with the extended Euclidean algorithm
+// Returns None if a and m are not coprime
+pub fn mod_inverse(a: &Int, m: &Int) -> Option<Int> {
+    // Invariant: old_s*a = old_r and s*a = r, in modulo m
+    let (mut old_r, mut r) = (a % m, m.clone());
+    let (mut old_s, mut s) = (Int::one(), Int::zero());
+    while !r.is_zero() {
+        let q = &old_r / &r;
+        let next_r = &old_r - &q * &r;
+        old_r = std::mem::replace(&mut r, next_r);
+        let next_s = &old_s - &q * &s;
+        old_s = std::mem::replace(&mut s, next_s);
+    }
+
+    // old_r is gcd(a, m), up to the sign
+    if old_r == 1 || old_r == -1 {
+        // Bring the result in 0..m
+        let inverse = (old_s * old_r) % m;
+        Some(if inverse < 0 { inverse + m } else { inverse })
+    } else {
+        None
+    }
+}
+
+// Floor of the logarithm of a in base b (with a > 0 and b > 1)
+// counts how many times a can be divided by b before dropping below 1
+// Returns None if interrupted
+pub fn integer_log(mut a: Int, b: &Int) -> Option<Int> {
+    let mut log = Int::zero();
+    while a >= *b {
+        if interrupted() {
+            return None;
+        }
+        a /= b;
+        log += 1;
+    }
+    Some(log)
+}
+
+// Power tower of n copies of a, a^a^...^a
+// Returns None if the result would have more than max_bits bits
+pub fn tetration(a: &Int, n: &Int, max_bits: usize) -> Option<Int> {
+    // 1^^n is always 1, 0^^n alternates between 1 and 0 (because 0^0 is 1)
+    if *a < 2 {
+        return Some(if a.is_zero() && !n.is_even() {
+            Int::zero()
+        } else {
+            Int::one()
+        });
+    }
+
+    // Each level is at least a^2 times bigger than the previous, so only few levels fit
+    let bits = Int::from(a.bit_length());
+    let mut result = Int::one();
+    let mut level = Int::zero();
+    while level < *n {
+        // a^result has at most result*bits(a) bits
+        if &result * &bits > max_bits {
+            return None;
+        }
+        result = a.pow(usize::from(&result));
+        level += 1;
+    }
+    Some(result)
+}
+
+#[inline]
+pub fn lcm(a: Int, b: Int) -> Int {
+    if a.is_zero() || b.is_zero() {
+        return Int::zero();
+    }
+
+    let d = gcd(a.clone(), b.clone());
+    a / d * b
+}
+
+// Parse a number literal, both numerator and denominator can have decimal digits
+// Returns None if the denominator is zero
+#[inline]
+pub fn from_decimal(literal: &str) -> Option<Rational> {
+    let mut parts = literal.split('/').map(|part| {
+        // Int doesn't accept an explicit plus
+        let part = part.trim_start_matches('+');
+        if let Some(point) = part.find('.') {
+            // Drop the point and divide by the right power of ten
+            let digits = part.len() - point - 1;
+            let num = Int::from_str(&[&part[..point], &part[point + 1..]].concat()).ok()?;
+            Some(Rational::new(num, Int::from(10).pow(digits)))
+        } else {
+            Some(Rational::from(Int::from_str(part).ok()?))
+        }
+    });
+
+    let num = parts.next()??;
+    if let Some(den) = parts.next() {
+        let den = den?;
+        if den.is_zero() {
+            None
+        } else {
+            Some(num / den)
+        }
+    } else {
+        Some(num)
+    }
+}
+
+// Drop the underscores that separate the digits of a literal
+// Returns None if an underscore is not between two digits (like in 5_ or 1__0)
+pub fn without_separators(literal: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = literal.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_'
+            && (i == 0
+                || i + 1 == chars.len()
+                || !chars[i - 1].is_digit(radix)
+                || !chars[i + 1].is_digit(radix))
+        {
+            return None;
+        }
+    }
+    Some(literal.replace('_', ""))
+}
+
+// Parse a hexadecimal or binary literal, with an optional sign and denominator (-0xff/0x10)
+// every part starts with a two characters prefix, digits can be separated by underscores
+// Returns None if the denominator is zero
+pub fn from_radix(literal: &str, radix: u8) -> Option<Rational> {
+    let negative = literal.starts_with('-');
+    let mut parts = literal
+        .trim_start_matches(&['-', '+'][..])
+        .split('/')
+        .map(|part| Int::from_str_radix(&part[2..].replace('_', ""), radix).ok());
+
+    let num = parts.next()??;
+    let num = if negative { -num } else { num };
+    if let Some(den) = parts.next() {
+        let den = den?;
+        if den.is_zero() {
+            None
+        } else {
+            Some(Rational::new(num, den))
+        }
+    } else {
+        Some(Rational::from(num))
+    }
+}
+
+// Smallest k > 0 such that base^k = 1 in modulo n
+// base and n must be coprime, returns 0 if n is 1
+// every multiplication is added to steps, returns None if interrupted or if steps exceeds max_steps
+#[inline]
+pub fn multiplicative_order(base: &Int, n: &Int, steps: &mut u64, max_steps: u64) -> Option<Int> {
+    let mut order = Int::zero();
+    if n.is_one() {
+        return Some(order);
+    }
+
+    let base = base % n;
+    let mut power = Int::one();
+    loop {
+        *steps += 1;
+        if interrupted() || *steps > max_steps {
+            return None;
+        }
+        power = power * &base % n;
+        order += 1;
+        if power.is_one() {
+            return Some(order);
+        }
+    }
+}
+
+// Long division of a rational, truncated to a fixed number of decimal digits
+#[inline]
+pub fn to_decimal(x: Rational, digits: usize) -> String {
+    let sign = if x < Rational::zero() { "-" } else { "" };
+    let (num, den) = x.abs().into_parts();
+    let (int, rem) = num.divmod(&den);
+
+    if digits == 0 {
+        return format!("{}{}", sign, int);
+    }
+
+    // All the digits come from a single division
+    let frac = rem * Int::from(10).pow(digits) / den;
+    format!("{}{}.{:0>width$}", sign, int, frac, width = digits)
+}
+
+// Approximation of x as a double precision floating point number
+// Returns an error if x is too large or too small to be represented
+// numerator and denominator are shifted to 960 bits before converting them,
+// so that they can't become infinite by themselves
+pub fn to_f64(x: Rational) -> Result<f64, &'static str> {
+    let sign = if x < Rational::zero() { -1.0 } else { 1.0 };
+    let (num, den) = x.abs().into_parts();
+    if num.is_zero() {
+        return Ok(0.0);
+    }
+
+    // |x| is between 2^(exponent-1) and 2^(exponent+1)
+    let exponent = i64::from(num.bit_length()) - i64::from(den.bit_length());
+    if exponent > 1024 {
+        return Err("Value too large for f64, use cfrac or decimals");
+    }
+    if exponent < -1074 {
+        return Err("Value too small for f64, use cfrac or decimals");
+    }
+
+    let num_shift = num.bit_length().saturating_sub(960) as usize;
+    let den_shift = den.bit_length().saturating_sub(960) as usize;
+    let scale = num_shift as i32 - den_shift as i32;
+    let value = (num >> num_shift).to_f64() / (den >> den_shift).to_f64();
+    // Scaling in two steps, because 2^scale alone could overflow
+    let value = value * 2f64.powi(scale / 2) * 2f64.powi(scale - scale / 2);
+
+    if value.is_infinite() {
+        Err("Value too large for f64, use cfrac or decimals")
+    } else {
+        Ok(sign * value)
+    }
+}
+
+// Like to_decimal, but without trailing zeros
+// an ellipsis is added if the expansion doesn't end within the digits
+pub fn short_decimal(x: Rational, digits: usize) -> String {
+    let (_, den) = (x.clone() * Rational::from(Int::from(10).pow(digits))).into_parts();
+    let mut text = to_decimal(x, digits);
+
+    if den.is_one() {
+        if text.contains('.') {
+            let len = text.trim_end_matches('0').trim_end_matches('.').len();
+            text.truncate(len);
+        }
+    } else {
+        text.push('…');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(literal: &str) -> Int {
+        Int::from_str(literal).unwrap()
+    }
+
+    #[test]
+    fn gcd_and_lcm_with_zero() {
+        assert_eq!(gcd(Int::from(12), Int::zero()), 12);
+        assert_eq!(gcd(Int::zero(), Int::from(12)), 12);
+        assert_eq!(lcm(Int::from(12), Int::zero()), 0);
+        assert_eq!(lcm(Int::from(4), Int::from(6)), 12);
+    }
+
+    #[test]
+    fn gcd_and_lcm_of_large_coprimes() {
+        // Consecutive Fibonacci numbers are coprime, and the slowest case of the Euclidean algorithm
+        let a = fibonacci(&Int::from(478), usize::MAX).unwrap();
+        let b = fibonacci(&Int::from(479), usize::MAX).unwrap();
+        assert_eq!(a.to_string().len(), 100);
+
+        assert_eq!(gcd(a.clone(), b.clone()), 1);
+        assert_eq!(lcm(a.clone(), b.clone()), &a * &b);
+    }
+
+    // Run with: cargo test bench -- --ignored --nocapture
+    // consecutive Fibonacci numbers take the most steps of the Euclidean algorithm
+    #[test]
+    #[ignore]
+    fn bench_gcd_of_large_coprimes() {
+        let a = fibonacci(&Int::from(478), usize::MAX).unwrap();
+        let b = fibonacci(&Int::from(479), usize::MAX).unwrap();
+
+        let start = std::time::Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(gcd(a.clone(), b.clone()), 1);
+        }
+        println!("gcd of 100 digits coprimes: {:?}", start.elapsed() / 1000);
+    }
+
+    #[test]
+    fn gcd_of_large_multiples() {
+        let a = int("1000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007");
+        let b = &a + 1;
+        assert_eq!(gcd(&a * 35, &b * 21), 7);
+        assert_eq!(lcm(&a * 2, &b * 2), a * b * 2);
+    }
+
+    #[test]
+    fn decimal_literals() {
+        let parse = |literal| from_decimal(literal).map(|x| Number(x).to_string());
+        assert_eq!(parse("3.14").as_deref(), Some("157/50"));
+        assert_eq!(parse("-0.25").as_deref(), Some("-1/4"));
+        assert_eq!(parse("+2.50").as_deref(), Some("5/2"));
+        assert_eq!(parse("1.5/0.5").as_deref(), Some("3"));
+        assert_eq!(parse("1/0.0"), None);
+    }
+
+    #[test]
+    fn decimal_expansion() {
+        let seventh = Rational::new(Int::from(1), Int::from(7));
+        let approx = Rational::new(Int::from(22), Int::from(7));
+        assert_eq!(to_decimal(seventh.clone(), 18), "0.142857142857142857");
+        assert_eq!(to_decimal(approx.clone(), 18), "3.142857142857142857");
+        assert_eq!(to_decimal(-approx, 3), "-3.142");
+        // Digits are truncated, not rounded
+        assert_eq!(to_decimal(seventh.clone(), 5), "0.14285");
+        assert_eq!(to_decimal(seventh, 0), "0");
+        assert_eq!(
+            to_decimal(Rational::new(Int::from(1), Int::from(100)), 4),
+            "0.0100"
+        );
+    }
+
+    #[test]
+    fn factorial_of_small_numbers() {
+        assert_eq!(factorial(&Int::zero()).unwrap(), 1);
+        assert_eq!(factorial(&Int::one()).unwrap(), 1);
+        assert_eq!(factorial(&Int::from(5)).unwrap(), 120);
+        assert_eq!(
+            factorial(&Int::from(20)).unwrap(),
+            Int::from(2432902008176640000u64)
+        );
+    }
+
+    #[test]
+    fn binomial_is_symmetric() {
+        let n = Int::from(60);
+        for k in 0..=60 {
+            let k = Int::from(k);
+            assert_eq!(binomial(&n, &k), binomial(&n, &(&n - &k)));
+        }
+        assert_eq!(
+            binomial(&n, &Int::from(30)).unwrap(),
+            int("118264581564861424")
+        );
+        assert_eq!(permutations(&n, &Int::zero()).unwrap(), 1);
+    }
+
+    #[test]
+    fn primes() {
+        let primes: Vec<u32> = (0..60)
+            .filter(|&n| is_prime(&Int::from(n)).unwrap())
+            .collect();
+        assert_eq!(
+            primes,
+            [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59]
+        );
+        // Carmichael numbers fool the Fermat test
+        assert_eq!(is_prime(&Int::from(561)), Some(false));
+        assert_eq!(is_prime(&Int::from(41041)), Some(false));
+        assert_eq!(is_prime(&int("3215031751")), Some(false));
+    }
+
+    #[test]
+    fn separators_between_digits() {
+        assert_eq!(
+            without_separators("1_000_000", 10).as_deref(),
+            Some("1000000")
+        );
+        assert_eq!(
+            without_separators("-1_0.2_5/3", 10).as_deref(),
+            Some("-10.25/3")
+        );
+        assert_eq!(without_separators("0xff_ff", 16).as_deref(), Some("0xffff"));
+        for literal in ["_5", "5_", "1__0", "1_/2", "1/_2", "1_.5", "-_5"].iter() {
+            assert_eq!(without_separators(literal, 10), None);
+        }
+        assert_eq!(without_separators("0x_ff", 16), None);
+        assert_eq!(without_separators("0b1_2", 2), None);
+    }
+
+    #[test]
+    fn continued_fractions() {
+        let expand = |num: i32, den: i32| -> Vec<Int> {
+            continued_fraction(Rational::new(Int::from(num), Int::from(den)))
+        };
+        assert_eq!(expand(22, 7), [3, 7]);
+        assert_eq!(expand(355, 113), [3, 7, 16]);
+        assert_eq!(expand(-7, 3), [-3, 1, 2]);
+        assert_eq!(expand(1, 3), [0, 3]);
+        assert_eq!(expand(5, 1), [5]);
+        // Consecutive Fibonacci numbers give only ones, but the last
+        assert_eq!(expand(89, 55), [1, 1, 1, 1, 1, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn short_decimals() {
+        let third = Rational::new(Int::from(1), Int::from(3));
+        assert_eq!(short_decimal(third.clone(), 3), "0.333…");
+        assert_eq!(short_decimal(-third, 0), "-0…");
+        assert_eq!(
+            short_decimal(Rational::new(Int::from(5), Int::from(4)), 3),
+            "1.25"
+        );
+        assert_eq!(
+            short_decimal(Rational::new(Int::from(5), Int::from(4)), 1),
+            "1.2…"
+        );
+        assert_eq!(short_decimal(Rational::from(Int::from(20)), 3), "20");
+    }
+
+    fn power_of_ten(exponent: usize) -> Rational {
+        Rational::new(Int::from(10).pow(exponent), Int::from(1))
+    }
+
+    #[test]
+    fn f64_out_of_range() {
+        let huge = power_of_ten(400);
+        assert!(to_f64(huge.clone()).is_err());
+        assert!(to_f64(-huge.clone()).is_err());
+        assert!(to_f64(Rational::one() / huge).is_err());
+        // Subnormals are still numbers
+        let tiny = Rational::one() / power_of_ten(320);
+        assert_eq!(to_f64(tiny.clone()), Ok(1e-320));
+        assert_eq!(to_f64(-tiny), Ok(-1e-320));
+    }
+
+    #[test]
+    fn f64_in_range() {
+        assert_eq!(to_f64(power_of_ten(308)), Ok(1e308));
+        assert_eq!(to_f64(Rational::new(Int::from(3), Int::from(2))), Ok(1.5));
+        assert_eq!(to_f64(Rational::zero()), Ok(0.0));
+        // Both parts are too large, their quotient is not
+        let big = power_of_ten(400);
+        assert_eq!(to_f64(big.clone() / (big * Rational::from(4))), Ok(0.25));
+    }
+}
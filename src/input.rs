@@ -1,47 +1,81 @@
 use std::borrow::Cow::{self, Borrowed, Owned};
 
+use crate::calculator::Token;
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
-use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use logos::Logos;
+use rustyline::completion::{Completer, Pair};
 use rustyline::config::OutputStreamType;
 use rustyline::error::ReadlineError;
-use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::highlight::Highlighter;
 use rustyline::hint::{Hinter, HistoryHinter};
 use rustyline::validate::{self, Validator};
 use rustyline::{Cmd, CompletionType, Config, Context, EditMode, Editor, KeyEvent};
 use rustyline_derive::Helper;
+use std::env;
 use std::path::{Path, PathBuf};
 
 pub type Edit = Editor<MyHelper>;
 
+// Default prompt, it can be changed from the command line
+pub const PROMPT: &str = "λ> ";
+// Prompt shown while reading a {{ }} block
+pub const BLOCK_PROMPT: &str = "... ";
+// Prompt shown while a function declaration is still missing its name
+pub const CONTINUATION_PROMPT: &str = "...> ";
+
 lazy_static! {
     pub static ref DIRS: Option<ProjectDirs> = ProjectDirs::from("com", "rpn-lang", "rpn-c");
     pub static ref DATA_LOCAL_DIR: Option<PathBuf> = DIRS
         .as_ref()
         .and_then(|dirs| Some(Path::new(dirs.data_local_dir()).to_path_buf()));
-    pub static ref HISTORY_PATH: Option<PathBuf> = DATA_LOCAL_DIR
-        .as_ref()
-        .and_then(|dir| Some(dir.join("history.txt")));
+    // RPN_HISTORY moves the history somewhere else
+    pub static ref HISTORY_PATH: Option<PathBuf> = env::var_os("RPN_HISTORY")
+        .map(PathBuf::from)
+        .or_else(|| DATA_LOCAL_DIR.as_ref().map(|dir| dir.join("history.txt")));
+    pub static ref TABLE_PATH: Option<PathBuf> =
+        DATA_LOCAL_DIR.as_ref().map(|dir| dir.join("table.rpnl"));
 }
 
 #[derive(Helper)]
 pub struct MyHelper {
-    completer: FilenameCompleter,
-    highlighter: MatchingBracketHighlighter,
+    // Names of the variables and functions, used for completion
+    pub names: Vec<String>,
     hinter: HistoryHinter,
+    prompt: String,
     colored_prompt: String,
 }
 
 impl Completer for MyHelper {
     type Candidate = Pair;
 
+    // Complete the identifier under the cursor with the defined names
     fn complete(
         &self,
         line: &str,
         pos: usize,
-        ctx: &Context<'_>,
+        _ctx: &Context<'_>,
     ) -> Result<(usize, Vec<Pair>), ReadlineError> {
-        self.completer.complete(line, pos, ctx)
+        let start = line[..pos]
+            .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .map_or(0, |index| index + 1);
+        let prefix = &line[start..pos];
+
+        // Identifiers always start with a letter
+        if !prefix.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Ok((pos, Vec::new()));
+        }
+
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name.clone(),
+            })
+            .collect();
+        Ok((start, candidates))
     }
 }
 
@@ -59,7 +93,7 @@ impl Highlighter for MyHelper {
         prompt: &'p str,
         default: bool,
     ) -> Cow<'b, str> {
-        if default {
+        if default && prompt == self.prompt {
             Borrowed(&self.colored_prompt)
         } else {
             Borrowed(prompt)
@@ -70,12 +104,63 @@ impl Highlighter for MyHelper {
         Owned("\x1b[2m".to_owned() + hint + "\x1b[0m")
     }
 
-    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
-        self.highlighter.highlight(line, pos)
+    // Color each token according to its kind
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        // Block delimiters are not tokens
+        let trimmed = line.trim();
+        if trimmed == "{{" || trimmed == "}}" {
+            return Borrowed(line);
+        }
+
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut end = 0;
+        for (token, span) in Token::lexer(line).spanned() {
+            // Copy what the lexer skipped, dimming the comments
+            highlight_gap(&mut highlighted, &line[end..span.start]);
+
+            let slice = &line[span.clone()];
+            let color = match token {
+                // Strings are numbers too
+                Token::Number(_) if slice.starts_with('"') => "\x1b[32m",
+                Token::Number(_) => "\x1b[36m",
+                Token::Identifier(_)
+                | Token::AssignVariable(_)
+                | Token::KeepVariable(_)
+                | Token::AssignFunction(_)
+                | Token::AssignIterative(_)
+                | Token::Undefine(_)
+                | Token::Inverse(_) => "\x1b[33m",
+                Token::Argument(_) => "\x1b[35m",
+                Token::Error => "\x1b[31m",
+                // Operators and commands
+                _ => "\x1b[1m",
+            };
+            highlighted.push_str(color);
+            highlighted.push_str(slice);
+            highlighted.push_str("\x1b[0m");
+            end = span.end;
+        }
+        highlight_gap(&mut highlighted, &line[end..]);
+
+        Owned(highlighted)
     }
 
-    fn highlight_char(&self, line: &str, pos: usize) -> bool {
-        self.highlighter.highlight_char(line, pos)
+    // Any character can change the tokens, so the line is always highlighted again
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+// Copies spaces and comments, comments are dimmed
+#[inline]
+fn highlight_gap(highlighted: &mut String, gap: &str) {
+    if let Some(comment) = gap.find(';') {
+        highlighted.push_str(&gap[..comment]);
+        highlighted.push_str("\x1b[2m");
+        highlighted.push_str(&gap[comment..]);
+        highlighted.push_str("\x1b[0m");
+    } else {
+        highlighted.push_str(gap);
     }
 }
 
@@ -94,23 +179,30 @@ impl Validator for MyHelper {
 }
 
 #[inline]
-pub fn new_editor() -> Editor<MyHelper> {
+pub fn new_editor(
+    edit_mode: EditMode,
+    prompt: &str,
+    history_size: Option<usize>,
+) -> Editor<MyHelper> {
     env_logger::init();
 
     // Build configuration
-    let config = Config::builder()
+    let mut config = Config::builder()
         .history_ignore_space(true)
         .completion_type(CompletionType::List)
-        .edit_mode(EditMode::Emacs)
-        .output_stream(OutputStreamType::Stdout)
-        .build();
+        .edit_mode(edit_mode)
+        .output_stream(OutputStreamType::Stdout);
+    if let Some(size) = history_size {
+        config = config.max_history_size(size);
+    }
+    let config = config.build();
 
     // Build helper struct
     let h = MyHelper {
-        completer: FilenameCompleter::new(),
-        highlighter: MatchingBracketHighlighter::new(),
+        names: Vec::new(),
         hinter: HistoryHinter {},
-        colored_prompt: "".to_owned(),
+        prompt: prompt.to_owned(),
+        colored_prompt: format!("\x1b[1;32m{}\x1b[0m", prompt),
     };
 
     // Configure editor with the above two
@@ -120,7 +212,79 @@ pub fn new_editor() -> Editor<MyHelper> {
     rl.bind_sequence(KeyEvent::alt('p'), Cmd::HistorySearchBackward);
     rl.bind_sequence(KeyEvent::ctrl('d'), Cmd::EndOfFile);
 
-    rl.helper_mut().expect("No helper").colored_prompt = format!("\x1b[1;32m{}\x1b[0m", "λ> ");
-
     rl
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::config::Configurer;
+    use rustyline::history::History;
+
+    fn helper(names: &[&str]) -> MyHelper {
+        MyHelper {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            hinter: HistoryHinter {},
+            prompt: PROMPT.to_owned(),
+            colored_prompt: PROMPT.to_owned(),
+        }
+    }
+
+    // Start of the replaced word and the candidates
+    fn complete(helper: &MyHelper, line: &str) -> (usize, Vec<String>) {
+        let history = History::new();
+        let (start, pairs) = helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        (
+            start,
+            pairs.into_iter().map(|pair| pair.replacement).collect(),
+        )
+    }
+
+    // The logger can be initialized only once, so every editor is built here
+    #[test]
+    fn editor_configuration() {
+        let mut rl = new_editor(EditMode::Vi, "calc> ", Some(10));
+        assert_eq!(rl.config_mut().edit_mode(), EditMode::Vi);
+        assert_eq!(rl.config_mut().max_history_size(), 10);
+
+        // The prompt given to readline is the one that gets colored
+        let helper = rl.helper().unwrap();
+        assert_eq!(helper.prompt, "calc> ");
+        assert_eq!(
+            helper.highlight_prompt("calc> ", true),
+            "\x1b[1;32mcalc> \x1b[0m"
+        );
+        assert_eq!(helper.highlight_prompt(PROMPT, true), PROMPT);
+        assert_eq!(helper.highlight_prompt("calc> ", false), "calc> ");
+    }
+
+    #[test]
+    fn completes_defined_names() {
+        let helper = helper(&["fib", "fib_rec", "gcd_aux", "x"]);
+        assert_eq!(
+            complete(&helper, "10 fi"),
+            (3, vec!["fib".to_string(), "fib_rec".to_string()])
+        );
+        assert_eq!(complete(&helper, "fib_"), (0, vec!["fib_rec".to_string()]));
+        assert_eq!(complete(&helper, "3 =x"), (3, vec!["x".to_string()]));
+        // Not the start of an identifier
+        assert_eq!(complete(&helper, "1 $1"), (4, Vec::new()));
+        assert_eq!(complete(&helper, "1 2f"), (4, Vec::new()));
+    }
+
+    #[test]
+    fn highlights_each_token() {
+        let helper = helper(&[]);
+        assert_eq!(
+            helper.highlight("2 x + @ ; sum", 0),
+            "\x1b[36m2\x1b[0m \x1b[33mx\x1b[0m \x1b[1m+\x1b[0m \x1b[31m@\x1b[0m \x1b[2m; sum\x1b[0m"
+        );
+        assert_eq!(
+            helper.highlight("\"ab\" $0 f|1", 0),
+            "\x1b[32m\"ab\"\x1b[0m \x1b[35m$0\x1b[0m \x1b[33mf|1\x1b[0m"
+        );
+        assert_eq!(helper.highlight(" {{", 0), " {{");
+    }
+}
@@ -14,66 +14,381 @@
 
 mod calculator;
 mod input;
-use calculator::Calculator;
-use input::{new_editor, Edit, DATA_LOCAL_DIR, HISTORY_PATH};
+use calculator::{normalize, Calculator, INTERRUPTED};
+use input::{
+    new_editor, Edit, BLOCK_PROMPT, CONTINUATION_PROMPT, DATA_LOCAL_DIR, HISTORY_PATH, PROMPT,
+    TABLE_PATH,
+};
 use rustyline::error::ReadlineError;
-use std::fs::create_dir_all;
+use rustyline::EditMode;
+use std::env;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::{stdin, Read};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+// Size of the stack of the thread running the calculator
+// a debug build overflows 8 MiB at about 900 nested operations, this leaves more than twice
+// what the highest maxdepth needs; the memory is only used if the recursion gets there
+const STACK_SIZE: usize = 128 << 20;
+
+// Options read from the command line
+struct Options {
+    // Expressions to evaluate without starting the REPL
+    eval: Vec<String>,
+    // Script files to load after the std_lib
+    scripts: Vec<PathBuf>,
+    // Use vi keybindings instead of emacs ones
+    vi: bool,
+    // Parse the whole stdin at once, without starting the REPL
+    batch: bool,
+    // Skip loading the std_lib
+    no_std: bool,
+    // File to load instead of the embedded std_lib
+    std_lib: Option<PathBuf>,
+    // Don't print the welcome message
+    quiet: bool,
+    // Where the history is kept, None to not keep it
+    history: Option<PathBuf>,
+    // Don't load or save the definitions of the previous session
+    no_table: bool,
+    // Number of lines kept in the history, rustyline's default if None
+    history_size: Option<usize>,
+    // Print the normalized input instead of evaluating it
+    format: bool,
+    prompt: String,
+}
+
+// Read options from the command line
+// Exits on invalid arguments
+fn parse_args() -> Options {
+    let mut options = Options {
+        eval: Vec::new(),
+        scripts: Vec::new(),
+        vi: env::var("RPN_EDIT_MODE").as_deref() == Ok("vi"),
+        batch: false,
+        no_std: false,
+        std_lib: env::var_os("RPN_STDLIB").map(PathBuf::from),
+        quiet: false,
+        history: HISTORY_PATH.clone(),
+        no_table: false,
+        history_size: None,
+        format: false,
+        prompt: env::var("RPN_PROMPT").unwrap_or_else(|_| String::from(PROMPT)),
+    };
+
+    if let Some(size) = env::var_os("RPN_HISTORY_SIZE") {
+        match size.to_str().and_then(|size| size.parse().ok()) {
+            Some(size) => options.history_size = Some(size),
+            None => {
+                eprintln!("Invalid RPN_HISTORY_SIZE {:?}", size);
+                exit(2);
+            }
+        }
+    }
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--eval" => {
+                if let Some(expression) = args.next() {
+                    options.eval.push(expression);
+                } else {
+                    eprintln!("Missing expression after {}", arg);
+                    exit(2);
+                }
+            }
+            "--prompt" => {
+                if let Some(prompt) = args.next() {
+                    options.prompt = prompt;
+                } else {
+                    eprintln!("Missing prompt after {}", arg);
+                    exit(2);
+                }
+            }
+            "--quiet" => options.quiet = true,
+            "--history" => {
+                if let Some(path) = args.next() {
+                    options.history = Some(PathBuf::from(path));
+                } else {
+                    eprintln!("Missing path after {}", arg);
+                    exit(2);
+                }
+            }
+            "--no-history" => options.history = None,
+            "--no-table" => options.no_table = true,
+            "--format" => options.format = true,
+            "--vi" => options.vi = true,
+            "--batch" => options.batch = true,
+            "--no-std" => options.no_std = true,
+            "--stdlib" => {
+                if let Some(path) = args.next() {
+                    options.std_lib = Some(PathBuf::from(path));
+                } else {
+                    eprintln!("Missing path after {}", arg);
+                    exit(2);
+                }
+            }
+            _ if !arg.starts_with('-') => options.scripts.push(PathBuf::from(arg)),
+            _ => {
+                eprintln!("Unrecognized argument {}", arg);
+                exit(2);
+            }
+        }
+    }
+
+    options
+}
 
 fn main() {
+    // The calculator runs in its own thread, only to get a bigger stack
+    let calculator = match thread::Builder::new().stack_size(STACK_SIZE).spawn(run) {
+        Ok(calculator) => calculator,
+        Err(err) => {
+            eprintln!("Unable to start the calculator: {}", err);
+            exit(1);
+        }
+    };
+
+    // A panic was already reported by the thread
+    if calculator.join().is_err() {
+        exit(101);
+    }
+}
+
+fn run() {
+    let options = parse_args();
+    if options.format {
+        format(&options);
+        return;
+    }
+
+    let std_lib = if options.no_std {
+        None
+    } else {
+        Some(read_std_lib(&options.std_lib))
+    };
+
+    // Creates calculator object
+    let mut calculator = Calculator::new();
+
+    // Evaluate the expressions one after the other (and then stdin, in batch mode), then quit
+    // The exit code tells if any of them was incomplete
+    if !options.eval.is_empty() || options.batch {
+        calculator.set_report_stack(false);
+        load_std_lib(&mut calculator, &std_lib, &options.std_lib);
+        load_scripts(&mut calculator, &options.scripts);
+
+        let mut complete = true;
+        for expression in options.eval {
+            complete &= calculator.parse(expression);
+        }
+        if options.batch {
+            let mut input = String::new();
+            if let Err(err) = stdin().read_to_string(&mut input) {
+                eprintln!("Unable to read stdin: {}", err);
+                exit(1);
+            }
+            complete &= calculator.parse(input);
+        }
+        exit(if complete { 0 } else { 1 });
+    }
+
     // Makes sure data_local_dir exists
     if let Some(path) = &*DATA_LOCAL_DIR {
         // It's not important if there's no history
         create_dir_all(path).unwrap_or_else(|_| {});
     }
 
-    // Creates calculator object and prompt
-    let mut calculator = Calculator::new();
-    let mut rl = new_editor();
+    // Creates prompt
+    let mut rl = new_editor(
+        if options.vi {
+            EditMode::Vi
+        } else {
+            EditMode::Emacs
+        },
+        &options.prompt,
+        options.history_size,
+    );
 
-    if let Some(path) = &*HISTORY_PATH {
-        if !path.exists() {}
-        rl.load_history(path)
-            .unwrap_or_else(|_| eprintln!("Unable to create local data dir"));
+    if let Some(path) = &options.history {
+        // A missing history is created when the prompt closes
+        if path.exists() {
+            rl.load_history(path)
+                .unwrap_or_else(|_| eprintln!("Unable to read history"));
+        }
     }
 
     // Print welcome
-    println!(
-        "Welcome to rpn-c {}\n press Ctrl-D to quit...",
-        env!("CARGO_PKG_VERSION")
-    );
+    if !options.quiet {
+        println!(
+            "Welcome to rpn-c {}\n press Ctrl-D to quit...",
+            env!("CARGO_PKG_VERSION")
+        );
+    }
 
-    #[cfg(unix)]
-    calculator.parse(String::from(include_str!("../std_lib.rpnl")));
+    load_std_lib(&mut calculator, &std_lib, &options.std_lib);
+    // The definitions that come from the std_lib will be loaded anyway, they are not saved
+    let std_table = calculator.snapshot();
+    let table_path = if options.no_table {
+        None
+    } else {
+        TABLE_PATH.as_ref()
+    };
+    if let Some(path) = table_path {
+        load_table(&mut calculator, path);
+    }
+    load_scripts(&mut calculator, &options.scripts);
 
-    #[cfg(windows)]
-    calculator.parse(String::from(include_str!("..\\std_lib.rpnl")));
+    // Ctrl-C while computing cancels the computation, instead of quitting
+    // (while reading a line, it's handled by the prompt)
+    ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed))
+        .unwrap_or_else(|_| eprintln!("Unable to handle Ctrl-C"));
 
     // REPL loop
-    repl(calculator, &mut rl);
+    repl(&mut calculator, &mut rl, &options.prompt);
 
     // Save history in the same file, if possible
-    if let Some(path) = &*HISTORY_PATH {
+    if let Some(path) = &options.history {
         rl.append_history(path)
             .unwrap_or_else(|_| eprintln!("Unable to append history"));
     }
+
+    // Save definitions for the next session, if possible
+    if let Some(path) = table_path {
+        write(path, calculator.save_table(Some(&std_table)))
+            .unwrap_or_else(|_| eprintln!("Unable to save definitions"));
+    }
+}
+
+// Restore the definitions of the previous session
+// It's not important if there are none
+#[inline]
+fn load_table(calculator: &mut Calculator, path: &Path) {
+    if let Ok(saved) = read_to_string(path) {
+        let skipped = calculator.restore_table(&saved);
+        if skipped > 0 {
+            eprintln!(
+                "Skipped {} invalid definitions in {}",
+                skipped,
+                path.display()
+            );
+        }
+    }
 }
 
+// The source of the std_lib, the embedded one unless a file is given
+// Exits if the file can't be read
 #[inline]
-fn repl(mut calculator: Calculator, rl: &mut Edit) {
+fn read_std_lib(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read std_lib {}: {}", path.display(), err);
+            exit(1);
+        }),
+        #[cfg(unix)]
+        None => String::from(include_str!("../std_lib.rpnl")),
+        #[cfg(windows)]
+        None => String::from(include_str!("..\\std_lib.rpnl")),
+    }
+}
+
+#[inline]
+fn load_std_lib(calculator: &mut Calculator, std_lib: &Option<String>, path: &Option<PathBuf>) {
+    if let Some(source) = std_lib {
+        calculator.load_std_lib(source.clone(), path.clone());
+    }
+}
+
+// Print the normalized form of the -e lines and the script files,
+// or of stdin if there are none
+fn format(options: &Options) {
+    for expression in &options.eval {
+        println!("{}", normalize(expression));
+    }
+    for path in &options.scripts {
+        match read_to_string(path) {
+            Ok(script) => println!("{}", normalize(&script)),
+            Err(err) => {
+                eprintln!("Unable to read {}: {}", path.display(), err);
+                exit(1);
+            }
+        }
+    }
+    if options.eval.is_empty() && options.scripts.is_empty() {
+        let mut input = String::new();
+        if let Err(err) = stdin().read_to_string(&mut input) {
+            eprintln!("Unable to read stdin: {}", err);
+            exit(1);
+        }
+        println!("{}", normalize(&input));
+    }
+}
+
+// Parse each script as a whole
+// Exits if one can't be read
+#[inline]
+fn load_scripts(calculator: &mut Calculator, scripts: &[PathBuf]) {
+    for path in scripts {
+        match read_to_string(path) {
+            Ok(script) => {
+                calculator.parse(script);
+            }
+            Err(err) => {
+                eprintln!("Unable to read {}: {}", path.display(), err);
+                exit(1);
+            }
+        }
+    }
+}
+
+#[inline]
+fn repl(calculator: &mut Calculator, rl: &mut Edit, prompt: &str) {
+    // Lines between {{ and }}, they get parsed all together when the block ends
+    let mut block: Option<String> = None;
+
     // REPL loop
     loop {
-        let readline = rl.readline("λ> ");
+        // Keep completion up to date with the definitions
+        rl.helper_mut().expect("No helper").names = calculator.names();
+
+        // The tokens of a declaration stay on the stack between lines,
+        // so it can be continued on the next one
+        let readline = rl.readline(if block.is_some() {
+            BLOCK_PROMPT
+        } else if calculator.in_declaration() {
+            CONTINUATION_PROMPT
+        } else {
+            prompt
+        });
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                calculator.parse(line);
+                let trimmed = line.trim();
+                if let Some(lines) = &mut block {
+                    if trimmed == "}}" {
+                        calculator.parse(block.take().unwrap());
+                    } else {
+                        lines.push_str(&line);
+                        lines.push('\n');
+                    }
+                } else if trimmed == "{{" {
+                    block = Some(String::new());
+                } else {
+                    calculator.parse(line);
+                }
             }
-            // Exit if the program is interrupted (Ctrl+C)
+            // Exit if the program is interrupted (Ctrl+C) while reading a line
             Err(ReadlineError::Interrupted) => {
                 break;
             }
             // Exit at end of file (which is caused by the end of a pipe or the input of Ctrl+D)
             Err(ReadlineError::Eof) => {
+                if block.is_some() {
+                    eprintln!("Unterminated block, dropped");
+                }
                 break;
             }
             // Report any other error
@@ -0,0 +1,649 @@
+mod common;
+
+use assert_cmd::Command;
+use common::*;
+use predicates::prelude::*;
+
+#[test]
+fn eval_prints_results() {
+    eval(&["2 3 + ="]).assert().success().stdout("> 5\n");
+}
+
+#[test]
+fn eval_lines_share_definitions() {
+    eval(&["2 3 + =x", "x 1 + ="])
+        .assert()
+        .success()
+        .stdout("> 6\n");
+}
+
+#[test]
+fn eval_fails_on_incomplete_expression() {
+    eval(&["2 +  ="])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("Incomplete expression"));
+}
+
+#[test]
+fn eval_fails_if_any_line_is_incomplete() {
+    eval(&["2 3 + =", "* =", "1 ="])
+        .assert()
+        .code(1)
+        .stdout("> 5\n> 1\n");
+}
+
+#[test]
+fn eval_loads_std_lib_first() {
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(["-e", "10 3 mod ="])
+        .assert()
+        .success()
+        .stdout("> 1\n");
+}
+
+#[test]
+fn eval_needs_an_expression() {
+    rpn()
+        .arg("-e")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Missing expression after -e"));
+}
+
+#[test]
+fn script_defines_functions() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("square.rpnl");
+    std::fs::write(&script, "; square\n$0 $0 * sq|1\n").unwrap();
+    rpn()
+        .arg(&script)
+        .args(["-e", "7 sq ="])
+        .assert()
+        .success()
+        .stdout("> 49\n");
+}
+
+#[test]
+fn missing_script_quits() {
+    let dir = tempfile::tempdir().unwrap();
+    rpn()
+        .arg(dir.path().join("missing.rpnl"))
+        .args(["-e", "1 ="])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("Unable to read"));
+}
+
+#[test]
+fn block_is_parsed_as_a_whole() {
+    let home = tempfile::tempdir().unwrap();
+    repl(
+        home.path(),
+        "{{\n$0 2 * dbl|1\n$0 dbl 1 + f|1\n}}\n3 f =\n2 dbl =\n",
+    )
+    .assert()
+    .success()
+    .stdout("0 elements in stack\n> 7\n0 elements in stack\n> 4\n0 elements in stack\n");
+}
+
+#[test]
+fn unterminated_block_is_dropped() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "{{\n1 2 + =\n")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains("Unterminated block, dropped"));
+}
+
+#[test]
+fn stack_report_toggle() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "1 2\nstackreport\n3\nstackreport 4\n")
+        .assert()
+        .success()
+        .stdout("2 elements in stack\n4 elements in stack\n");
+}
+
+#[test]
+fn stack_report_is_off_when_evaluating() {
+    eval(&["1 2", "stackreport 3"])
+        .assert()
+        .success()
+        .stdout("3 elements in stack\n");
+}
+
+#[test]
+fn saved_definitions_of_keywords_are_restored() {
+    let home = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(home.path().join("rpn-c")).unwrap();
+    std::fs::write(
+        home.path().join("rpn-c").join("table.rpnl"),
+        "$0 1 + abs|1\n",
+    )
+    .unwrap();
+    repl(home.path(), "-5 abs =\n")
+        .assert()
+        .success()
+        .stdout("> -4\n0 elements in stack\n")
+        .stderr("");
+}
+
+#[test]
+fn std_lib_constants() {
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(["-e", "pi [5] e [5] pi [50]", "-e", "3 =pi pi 1 + ="])
+        .assert()
+        .success()
+        .stdout(
+            "> 3.14159\n> 2.71828\n> 3.14159265358979323846264338327950288419716939937510\n> 4\n",
+        );
+}
+
+#[test]
+fn std_lib_exp_truncates_the_exponent() {
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(["-e", "2 -5/2 exp = 2 7/2 exp = 2 -5/2 7 mod_exp ="])
+        .assert()
+        .success()
+        .stdout("> 4\n> 8\n> 4\n");
+}
+
+#[test]
+fn definitions_last_across_sessions() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "$0 $0 * sq|1\n3 =x\n").assert().success();
+    repl(home.path(), "x sq =\n")
+        .assert()
+        .success()
+        .stdout("> 9\n0 elements in stack\n");
+}
+
+#[test]
+fn no_table_forgets_the_session() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "3 =x\n").assert().success();
+    let table = home.path().join("rpn-c").join("table.rpnl");
+    let saved = std::fs::read_to_string(&table).unwrap();
+    // Neither loaded nor overwritten
+    repl(home.path(), "dump\n4 =y\n")
+        .arg("--no-table")
+        .assert()
+        .success()
+        .stdout("0 elements in stack\n0 elements in stack\n");
+    assert_eq!(std::fs::read_to_string(&table).unwrap(), saved);
+}
+
+#[test]
+fn invalid_saved_definitions_are_skipped() {
+    let home = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(home.path().join("rpn-c")).unwrap();
+    std::fs::write(
+        home.path().join("rpn-c").join("table.rpnl"),
+        "$0 $0 * sq|1\n+ + bad|1\n3 sq =x\n",
+    )
+    .unwrap();
+    repl(home.path(), "x =\n")
+        .assert()
+        .success()
+        .stdout("> 9\n0 elements in stack\n")
+        .stderr(predicate::str::contains("Skipped 1 invalid definitions"));
+}
+
+#[test]
+fn block_comments_in_scripts() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("comments.rpnl");
+    std::fs::write(
+        &script,
+        ";{\n$0 2 * dbl|1\n;}\n$0 3 * tpl|1 ;{ blocks ;{ don't nest ;} $0 4 * qdr|1\n",
+    )
+    .unwrap();
+    rpn()
+        .arg(&script)
+        .args(["-e", "2 tpl = 2 qdr =", "-e", "2 dbl ="])
+        .assert()
+        .code(1)
+        .stdout("> 6\n> 8\n")
+        .stderr(predicate::str::contains("Incomplete expression"));
+}
+
+#[test]
+fn unterminated_block_comment_is_unrecognized() {
+    // The rest of the line is read as usual
+    eval(&["2 ;{ 3 + ="])
+        .assert()
+        .success()
+        .stdout("> 5\n")
+        .stderr("Error at 2..4: unrecognized ';{', dropped\n");
+}
+
+#[test]
+fn saved_session_loads_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("session.rpnl");
+    eval(&[
+        "$0 $0 * sq|1",
+        "5 =x 1 2 +",
+        &format!("save:{}", path.display()),
+    ])
+    .assert()
+    .success()
+    .stdout(format!("Saved to {}\n", path.display()));
+    rpn()
+        .arg(&path)
+        .args(["-e", ": x sq = ="])
+        .assert()
+        .success()
+        .stdout("1 2 + \n> 25\n> 3\n");
+}
+
+#[test]
+fn batch_prints_only_results() {
+    rpn()
+        .arg("--batch")
+        .write_stdin("2 3 + =")
+        .assert()
+        .success()
+        .stdout("> 5\n");
+}
+
+#[test]
+fn batch_fails_on_incomplete_expression() {
+    rpn()
+        .arg("--batch")
+        .write_stdin("1 =\n2 + =\n")
+        .assert()
+        .code(1)
+        .stdout("> 1\n");
+}
+
+#[test]
+fn no_std_leaves_only_builtins() {
+    eval(&["10 3 mod =", "pi =", "10 3 ieeerem ="])
+        .assert()
+        .code(1)
+        .stdout("> 1\n");
+}
+
+#[test]
+fn external_std_lib_replaces_the_embedded_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("lib.rpnl");
+    std::fs::write(&lib, "$0 3 * tpl|1\n").unwrap();
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .arg("--stdlib")
+        .arg(&lib)
+        .args(["-e", "2 tpl =", "-e", "10 3 mod ="])
+        .assert()
+        .code(1)
+        .stdout("> 6\n");
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .env("RPN_STDLIB", &lib)
+        .args(["-e", "2 tpl ="])
+        .assert()
+        .success()
+        .stdout("> 6\n");
+}
+
+#[test]
+fn missing_std_lib_quits() {
+    let dir = tempfile::tempdir().unwrap();
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .arg("--stdlib")
+        .arg(dir.path().join("missing.rpnl"))
+        .args(["-e", "1 ="])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("Unable to read std_lib"));
+}
+
+#[test]
+fn help_mentions_every_operator() {
+    let symbols = [
+        "+", "-", "*", "/", "~", "\\", "^", "_", "?", "$n", "=", "#", ":", ">", "<", "!", "%", "&",
+        "[]", "[n]", "=x", ":=x", "f|n", "f@n", "f^-1", "-x",
+    ];
+    let output = eval(&["help"]).output().unwrap();
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<&str> = help
+        .lines()
+        .filter_map(|line| line.split(' ').next())
+        .collect();
+    for symbol in symbols.iter() {
+        assert!(
+            rows.contains(symbol) || help.contains(&format!(" {} ", symbol)),
+            "{} is missing from help",
+            symbol
+        );
+    }
+}
+
+#[test]
+fn version_is_semver() {
+    eval(&["version"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(r"^rpn-c [0-9]+\.[0-9]+\.[0-9]+(-[0-9A-Za-z.-]+)?\n$")
+                .unwrap(),
+        )
+        .stdout(predicate::str::contains(env!("CARGO_PKG_VERSION")));
+}
+
+// Sends Ctrl-C to the REPL while it computes line, then computes 2 3 + =
+// Returns what was printed to stdout (after the first result) and stderr
+#[cfg(unix)]
+fn interrupt(line: &str) -> (String, String) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc::channel;
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+
+    let timeout = Duration::from_secs(60);
+    let home = tempfile::tempdir().unwrap();
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("rpn-c"))
+        .args(["--no-std", "--quiet", "--no-history"])
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // stdout is read in another thread, so that waiting for it can time out
+    let (sender, receiver) = channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    spawn(move || {
+        for line in stdout.lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // 0 is printed once Ctrl-C is handled and the line has started, right before line is computed
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "0 = {}", line).unwrap();
+    stdin.flush().unwrap();
+    match receiver.recv_timeout(timeout) {
+        Ok(first) => assert_eq!(first, "> 0"),
+        Err(_) => {
+            child.kill().unwrap();
+            panic!("The REPL didn't start computing {}", line);
+        }
+    }
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    // Ctrl-C doesn't quit, and the next line is computed as usual
+    stdin.write_all(b"2 3 + =\n").unwrap();
+    drop(stdin);
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("The REPL didn't stop computing {}", line);
+        }
+        sleep(Duration::from_millis(50));
+    };
+    assert!(status.success());
+
+    let stdout = receiver.iter().map(|line| line + "\n").collect();
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    (stdout, stderr)
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_cancels_the_rest_of_the_line() {
+    // A tail call that would loop for minutes, and a computation after it in the same line
+    let (stdout, stderr) = interrupt("$0 f f|1 1 f = 4 =");
+    assert!(stdout.contains("> 5\n"), "{}", stdout);
+    assert!(!stdout.contains("> 4\n"), "{}", stdout);
+    assert_eq!(
+        stderr.matches("Computation interrupted").count(),
+        1,
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_cancels_long_operators() {
+    for line in ["1000000 fact =", "1000000 500000 nCr ="].iter() {
+        let (stdout, stderr) = interrupt(line);
+        assert!(stdout.ends_with("> 5\n0 elements in stack\n"), "{}", stdout);
+        assert!(stderr.contains("Computation interrupted"), "{}", stderr);
+    }
+}
+
+#[test]
+fn quiet_hides_the_banner() {
+    let home = tempfile::tempdir().unwrap();
+    rpn()
+        .arg("--no-history")
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path())
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Welcome to rpn-c"));
+    repl(home.path(), "").assert().success().stdout("");
+}
+
+#[test]
+fn prompt_needs_a_value() {
+    rpn()
+        .arg("--prompt")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Missing prompt after --prompt"));
+}
+
+#[test]
+fn reload_reads_the_std_lib_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("lib.rpnl");
+    std::fs::write(&lib, "$0 3 * tpl|1\n$0 2 * dbl|1\n").unwrap();
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .arg("--stdlib")
+        .arg(&lib)
+        .args(["-e", "$0 4 * tpl|1 5 =x"])
+        // The session overwrites the std_lib with its own tpl
+        .args(["-e", &format!("save:{}", lib.display())])
+        .args(["-e", "$0 5 * tpl|1 $0 $0 * dbl|1 2 tpl = 3 dbl ="])
+        .args(["-e", "reload 2 tpl = 3 dbl = x ="])
+        .assert()
+        .success()
+        .stdout(format!(
+            "Saved to {}\n> 10\n> 9\nReloaded std_lib, 2 definitions changed\n> 8\n> 6\n> 5\n",
+            lib.display()
+        ));
+}
+
+#[test]
+fn reload_needs_a_std_lib() {
+    eval(&["reload"])
+        .assert()
+        .success()
+        .stderr("No std_lib to reload\n");
+}
+
+#[test]
+fn blank_lines_print_nothing() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "1\n   \n;; comment\n\n2\n")
+        .assert()
+        .success()
+        .stdout("1 elements in stack\n2 elements in stack\n");
+}
+
+#[test]
+fn std_lib_fibonacci_starts_from_one() {
+    // ifib, tfib and the fib operator all return the (n+1)-th Fibonacci number
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(["-e", "0 ifib = 10 ifib = 10 tfib = 0 fib = 10 fib ="])
+        .assert()
+        .success()
+        .stdout("> 1\n> 89\n> 89\n> 1\n> 89\n");
+}
+
+#[test]
+fn tail_calls_do_not_grow_the_stack() {
+    // Without tail call optimization tfib would overflow long before this
+    Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(["-e", "100000 tfib 100000 fib - ="])
+        .assert()
+        .success()
+        .stdout("> 0\n");
+}
+
+// Every kind of token, with comments and irregular spaces
+const UNFORMATTED: &str = "1 2 + 3 - 4 * 5 / 6 ~ 7 \\ 2 ^ 9 _ 1 0 ? =x ; comment\n\
+    x 2 absdiff    # 0.5 [] [3] : > < ! % & $0 $1 + f|2 $0 $1 - $2 g@2 :=y -x f^-1 \
+    \"a\" 0x1f 0b101 1_000 =  ;{ block ;}  abs sum";
+
+const FORMATTED: &str = "1 2 + 3 - 4 * 5 / 6 ~ 7 \\ 2 ^ 9 _ 1 0 ? =x\n\
+    x 2 absdiff # 1/2 [] [3] : > < ! % & $0 $1 + f|2 $0 $1 - $2 g@2 :=y -x f^-1 \
+    97 31 5 1000 = abs sum\n";
+
+#[test]
+fn format_normalizes_every_token() {
+    rpn()
+        .args(["--format", "-e", UNFORMATTED])
+        .assert()
+        .success()
+        .stdout(FORMATTED);
+}
+
+#[test]
+fn formatted_lines_read_back_the_same() {
+    rpn()
+        .args(["--format", "-e", FORMATTED.trim_end()])
+        .assert()
+        .success()
+        .stdout(FORMATTED);
+    let line = "1_000 0.25 * 3 ; comment\n - \"a\" + 0x10 ;{ block ;} - =";
+    let output = eval(&[line]).output().unwrap();
+    assert_eq!(output.stdout, b"> 328\n");
+    let formatted = rpn().args(["--format", "-e", line]).output().unwrap();
+    let formatted = String::from_utf8(formatted.stdout).unwrap();
+    eval(&[formatted.trim_end()])
+        .assert()
+        .success()
+        .stdout(String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn declaration_continues_on_the_next_line() {
+    let home = tempfile::tempdir().unwrap();
+    repl(home.path(), "$0 2\n* dbl|1\n3 dbl =\n")
+        .assert()
+        .success()
+        .stdout("2 elements in stack\n0 elements in stack\n> 6\n0 elements in stack\n");
+}
+
+#[test]
+fn dump_reloads_in_a_new_session() {
+    let output = eval(&[
+        "$0 1 + b|1 $0 b 2 * a|1 3 =x $0 $1 * $1 1 ~ $0 $1 f_aux@2 1 $0 f_aux f|1",
+        "dump",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let dump = String::from_utf8(output.stdout).unwrap();
+    // b comes before a, that uses it
+    assert!(dump.find("b|1") < dump.find("a|1"), "{}", dump);
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("dump.rpnl");
+    std::fs::write(&script, dump).unwrap();
+    rpn()
+        .arg(&script)
+        .args(["-e", "x a = 5 f ="])
+        .assert()
+        .success()
+        .stdout("> 8\n> 120\n")
+        .stderr("");
+}
+
+#[test]
+fn history_goes_where_it_is_asked() {
+    let home = tempfile::tempdir().unwrap();
+    let from_env = home.path().join("env_history.txt");
+    let from_arg = home.path().join("arg_history.txt");
+    rpn()
+        .arg("--quiet")
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path())
+        .env("RPN_HISTORY", &from_env)
+        .write_stdin("1 2 + =\n")
+        .assert()
+        .success();
+    assert!(std::fs::read_to_string(&from_env)
+        .unwrap()
+        .contains("1 2 + ="));
+    // The argument wins over the variable
+    std::fs::remove_file(&from_env).unwrap();
+    rpn()
+        .arg("--quiet")
+        .arg("--history")
+        .arg(&from_arg)
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path())
+        .env("RPN_HISTORY", &from_env)
+        .write_stdin("3 4 + =\n")
+        .assert()
+        .success();
+    assert!(std::fs::read_to_string(&from_arg)
+        .unwrap()
+        .contains("3 4 + ="));
+    assert!(!from_env.exists());
+}
+
+#[test]
+fn no_history_writes_no_history() {
+    let home = tempfile::tempdir().unwrap();
+    let history = home.path().join("history.txt");
+    rpn()
+        .args(["--quiet", "--no-history"])
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path())
+        .env("RPN_HISTORY", &history)
+        .write_stdin("1 2 + =\n")
+        .assert()
+        .success()
+        .stdout("> 3\n0 elements in stack\n");
+    assert!(!history.exists());
+}
@@ -0,0 +1,992 @@
+mod common;
+
+use common::*;
+use predicates::prelude::*;
+
+#[test]
+fn weighted_sum_of_pairs() {
+    eval(&["2 3 4 5 weightedsum"])
+        .assert()
+        .success()
+        .stdout("> 26\n");
+}
+
+#[test]
+fn weighted_sum_keeps_odd_stack() {
+    eval(&["2 3 4 weightedsum :"])
+        .assert()
+        .stdout("2 3 4 \n")
+        .stderr(predicate::str::contains("even number of values"));
+}
+
+#[test]
+fn weighted_sum_keeps_incomplete_stack() {
+    eval(&["+ 2 3 weightedsum :"])
+        .assert()
+        .code(1)
+        .stdout("+ 2 3 \n")
+        .stderr(predicate::str::contains("stack left untouched"));
+}
+
+#[test]
+fn undefine_then_reuse_variable() {
+    eval(&["2 =x", "-x", "x =", "3 =x", "x ="])
+        .assert()
+        .code(1)
+        .stdout("Removed x\n> 3\n")
+        .stderr(predicate::str::contains("Incomplete expression"));
+}
+
+#[test]
+fn undefine_function_used_by_another() {
+    eval(&[
+        "$0 2 * dbl|1",
+        "$0 dbl 1 + f|1",
+        "-dbl",
+        "3 f =",
+        "$0 3 * dbl|1",
+        "3 f =",
+    ])
+    .assert()
+    .code(1)
+    .stdout("Removed dbl\n> 10\n");
+}
+
+#[test]
+fn undefine_missing_name() {
+    eval(&["-y"])
+        .assert()
+        .stderr(predicate::str::contains("No such definition"));
+}
+
+#[test]
+fn steps_prints_each_operation() {
+    eval(&["2 3 + 4 * steps"])
+        .assert()
+        .success()
+        .stdout("+ -> 5\n* -> 20\n");
+}
+
+#[test]
+fn steps_leaves_expression_in_stack() {
+    eval(&["2 3 + steps ="])
+        .assert()
+        .success()
+        .stdout("+ -> 5\n> 5\n");
+}
+
+#[test]
+fn steps_only_computes_the_chosen_arm() {
+    // x is not defined, computing it would fail
+    eval(&["x 1 0 ? steps", "0 x and steps"])
+        .assert()
+        .success()
+        .stdout("? -> 1\nand -> 0\n");
+}
+
+#[test]
+fn steps_is_a_single_computation_for_maxsteps() {
+    // Each call takes less than 1000 steps, all of them together take more
+    eval(&[
+        "1000 maxsteps $0 1 ~ down 0 $0 ? down|1",
+        "100 down steps %",
+        "100 down 100 down + 100 down + steps",
+    ])
+    .assert()
+    .code(1)
+    .stdout(predicate::str::starts_with("down -> 0\n"))
+    .stderr(predicate::str::contains("Recursion limit exceeded"));
+}
+
+#[test]
+fn steps_follows_output_redirection() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("steps.txt");
+    eval(&[&format!("output:{} 2 3 + steps close", path.display())])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "+ -> 5\n");
+}
+
+#[test]
+fn min_max_of_pushed_numbers() {
+    eval(&["trackminmax 3 1 4"])
+        .assert()
+        .success()
+        .stdout("min 1 max 4\n");
+}
+
+#[test]
+fn min_max_only_while_tracking() {
+    eval(&["1/2 -1/3 trackminmax 3 1 4 + 0.5 trackminmax minmax"])
+        .assert()
+        .success()
+        .stdout("min 1/2 max 4\n");
+}
+
+#[test]
+fn min_max_reset() {
+    eval(&["trackminmax 3 1 4 resetminmax"])
+        .assert()
+        .success()
+        .stdout("No numbers tracked\n");
+}
+
+#[test]
+fn hex_and_binary_literals() {
+    eval(&["0xff = 0b1010 = 0x1_ff = -0x10 ="])
+        .assert()
+        .success()
+        .stdout("> 255\n> 10\n> 511\n> -16\n");
+}
+
+#[test]
+fn hex_output_reads_back() {
+    for value in ["255", "0", "-4096", "-255/16", "1/3"].iter() {
+        let output = eval(&[&format!("{} hex", value)]).output().unwrap();
+        let hex = String::from_utf8(output.stdout).unwrap();
+        let hex = hex.trim().trim_start_matches("> ");
+        eval(&[&format!("{} =", hex)])
+            .assert()
+            .success()
+            .stdout(format!("> {}\n", value));
+    }
+}
+
+#[test]
+fn exact_decimal_digits() {
+    eval(&["1 3 / [10] 22 7 / [] 22 7 / [12]"])
+        .assert()
+        .success()
+        .stdout("> 0.3333333333\n> 3.142857142857143e0\n> 3.142857142857\n");
+}
+
+#[test]
+fn invert_square_finds_square_root() {
+    // 2897/2048 is about 1.41455, sqrt(2) is about 1.41421
+    eval(&["$0 $0 * sq|1", "2 0 2 1/1000 sq^-1"])
+        .assert()
+        .success()
+        .stdout("> 2897/2048\n");
+}
+
+#[test]
+fn invert_decreasing_function() {
+    // About 1.9999999
+    eval(&["$0 $0 * neg sq|1", "-4 0 3 1/1000000 sq^-1"])
+        .assert()
+        .success()
+        .stdout("> 16777215/8388608\n");
+}
+
+#[test]
+fn invert_keeps_stack_on_invalid_arguments() {
+    for (arguments, error) in [
+        ("0 2 1/1000", "Incomplete expression"),
+        ("2 2 0 1/1000", "Lo is greater than hi"),
+        ("9 0 2 1/1000", "The target is not between"),
+        ("2 0 2 0", "Tolerance must be positive"),
+        ("2 0 2 x", "Unable to compute expression"),
+    ]
+    .iter()
+    {
+        eval(&["$0 $0 * sq|1", &format!("{} sq^-1 :", arguments)])
+            .assert()
+            .code(1)
+            .stdout(format!("{} \n", arguments))
+            .stderr(predicate::str::contains(*error));
+    }
+}
+
+#[test]
+fn comparisons_are_binary_expressions() {
+    eval(&["1 3 2 gt ! :", "$0 neg $0 $0 0 lt ? myabs|1 -5 myabs ="])
+        .assert()
+        .success()
+        .stdout("1 \n> 5\n");
+}
+
+#[test]
+fn definitions_hide_keywords() {
+    eval(&["$0 1 + abs|1", "-5 abs =", "-abs", "-5 abs ="])
+        .assert()
+        .success()
+        .stdout("> -4\nRemoved abs\n> 5\n");
+}
+
+#[test]
+fn old_std_lib_abs_still_works() {
+    eval(&["$0 $0 -1 * $0 0 ~ ? abs|1", "-5 abs = 3 abs =", "dump"])
+        .assert()
+        .success()
+        .stdout("> 5\n> 3\n$0 $0 -1 * $0 0 ~ ? abs|1\n");
+}
+
+#[test]
+fn definitions_dont_hide_other_keywords() {
+    // Only the operators that replaced std_lib functions can be hidden
+    eval(&["$0 1 + floor|1", "-5/2 floor =", "5 =sum", "1 2 sum"])
+        .assert()
+        .success()
+        .stdout("> -3/2\n> 3\n");
+}
+
+#[test]
+fn factorial_is_exact() {
+    eval(&["50 fact ="])
+        .assert()
+        .success()
+        .stdout("> 30414093201713378043612608166064768844377641568960512000000000000\n");
+}
+
+#[test]
+fn factorial_floors_its_operand() {
+    eval(&["7/2 fact = -3 fact ="])
+        .assert()
+        .success()
+        .stdout("> 6\n> 6\n")
+        .stderr(predicate::str::contains(
+            "Operand was not an integer in factorial",
+        ))
+        .stderr(predicate::str::contains(
+            "Operand was not positive in factorial",
+        ));
+}
+
+#[test]
+fn old_std_lib_fact_still_works() {
+    eval(&[
+        "$0 $1 * $1 1 ~ $0 $1 fact_aux@2",
+        "1 $0 fact_aux fact|1",
+        "5 fact =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 120\n");
+}
+
+#[test]
+fn combinations_and_permutations() {
+    eval(&["52 5 nCr = 10 3 nPr = 5 8 nCr = 5 8 nPr ="])
+        .assert()
+        .success()
+        .stdout("> 2598960\n> 720\n> 0\n> 0\n");
+}
+
+#[test]
+fn prime_test() {
+    eval(&[
+        "97 isprime = 91 isprime =",
+        "170141183460469231731687303715884105727 isprime =",
+        "170141183460469231731687303715884105729 isprime =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 1\n> 0\n> 1\n> 0\n");
+}
+
+const NFIB: &str = "$0 1 - nfib $0 2 - nfib + $0 $0 1 ~ ? nfib|1";
+
+#[test]
+fn memo_gives_the_same_results() {
+    eval(&[NFIB, "15 nfib = memo 15 nfib = 15 nfib = memo 15 nfib ="])
+        .assert()
+        .success()
+        .stdout("> 610\n> 610\n> 610\n> 610\n");
+}
+
+#[test]
+fn memo_makes_recursion_linear() {
+    // Without memo this would take about 10^12 calls
+    eval(&[NFIB, "memo 60 nfib ="])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("> 1548008755920\n");
+}
+
+#[test]
+fn memo_forgets_redefined_functions() {
+    eval(&["memo $0 2 * f|1", "3 f =", "$0 3 * f|1", "3 f ="])
+        .assert()
+        .success()
+        .stdout("> 6\n> 9\n");
+}
+
+#[test]
+fn cached_trees_give_the_same_results() {
+    // More expressions than the cache holds, evaluated twice
+    let expressions: Vec<String> = (0..100).map(|i| format!("{} {} * 1 + =", i, i)).collect();
+    let expected: String = (0..100).map(|i| format!("> {}\n", i * i + 1)).collect();
+    let mut lines: Vec<&str> = expressions.iter().map(String::as_str).collect();
+    lines.extend(expressions.iter().map(String::as_str));
+    eval(&lines)
+        .assert()
+        .success()
+        .stdout(format!("{}{}", expected, expected));
+}
+
+#[test]
+fn cached_trees_see_new_definitions() {
+    // The keyword and the function print the same, but they are cached apart
+    eval(&["-5 abs =", "$0 1 + abs|1", "-5 abs =", "-abs", "-5 abs ="])
+        .assert()
+        .success()
+        .stdout("> 5\n> -4\nRemoved abs\n> 5\n");
+}
+
+#[test]
+fn runaway_recursion_fails_without_crashing() {
+    eval(&["$0 f 1 + f|1", "1 f =", "2 3 + ="])
+        .assert()
+        .code(1)
+        .stdout("> 5\n")
+        .stderr(predicate::str::contains("Recursion limit exceeded"));
+}
+
+#[test]
+fn default_depth_allows_thousands_of_calls() {
+    eval(&["$0 1 ~ s $0 + 0 $0 ? s|1", "3000 s ="])
+        .assert()
+        .success()
+        .stdout("> 4501500\n");
+}
+
+#[test]
+fn deepest_recursion_fits_in_the_stack() {
+    eval(&["5000 maxdepth", "$0 f 1 + f|1", "1 f =", "5001 maxdepth"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Recursion limit exceeded"))
+        .stderr(predicate::str::contains("Depth is too big"));
+}
+
+#[test]
+fn runaway_tail_recursion_stops() {
+    eval(&["1000 maxsteps", "$0 f f|1", "1 f =", "2 3 + ="])
+        .assert()
+        .code(1)
+        .stdout("> 5\n")
+        .stderr(predicate::str::contains("Recursion limit exceeded"));
+}
+
+#[test]
+fn unrecognized_tokens_show_their_position() {
+    eval(&["2 @ 3 + =", "2 3 é + ="])
+        .assert()
+        .success()
+        .stdout("> 5\n> 5\n")
+        .stderr(
+            "Error at 2..3: unrecognized '@', dropped\nError at 4..6: unrecognized 'é', dropped\n",
+        );
+}
+
+#[test]
+fn clear_but_keeps_the_top_expression() {
+    eval(&["1 2 + 3 4 * 5 6 - clearbut :"])
+        .assert()
+        .success()
+        .stdout("5 6 - \n");
+}
+
+#[test]
+fn clear_but_keeps_incomplete_stack() {
+    eval(&["1 2 3 + * + clearbut :"])
+        .assert()
+        .code(1)
+        .stdout("1 2 3 + * + \n")
+        .stderr(predicate::str::contains("stack left untouched"));
+}
+
+#[test]
+fn tree_prints_without_consuming() {
+    eval(&["2 3 + 4 * tree ="])
+        .assert()
+        .success()
+        .stdout("*\n  +\n    2\n    3\n  4\n> 20\n");
+}
+
+#[test]
+fn underscores_separate_digits() {
+    eval(&["1_000 = 1_000/2_0 = 0xff_ff = 0b1_0 ="])
+        .assert()
+        .success()
+        .stdout("> 1000\n> 50\n> 65535\n> 2\n");
+}
+
+#[test]
+fn misplaced_underscores_are_unrecognized() {
+    for literal in ["_5", "5_", "1__0", "1_/2", "0x_ff"].iter() {
+        eval(&[&format!("{} 2 :", literal)])
+            .assert()
+            .success()
+            .stdout("2 \n")
+            .stderr(format!(
+                "Error at 0..{}: unrecognized '{}', dropped\n",
+                literal.len(),
+                literal
+            ));
+    }
+}
+
+#[test]
+fn depth_counts_expressions() {
+    eval(&["1 2 3 + 4 5 * 6 - depth = :", "% depth ="])
+        .assert()
+        .success()
+        .stdout("> 3\n1 2 3 + 4 5 * 6 - \n> 0\n");
+}
+
+#[test]
+fn depth_stops_at_incomplete_expressions() {
+    eval(&["+ 1 2 depth :"])
+        .assert()
+        .success()
+        .stdout("+ 1 2 2 \n");
+}
+
+#[test]
+fn mixed_numbers() {
+    eval(&["7/2 mixed -7/2 mixed 4 mixed -1/3 mixed"])
+        .assert()
+        .success()
+        .stdout("> 3 1/2\n> -3 1/2\n> 4\n> -1/3\n");
+}
+
+#[test]
+fn grouped_digits() {
+    eval(&[
+        "group 1000000 = 1000000/1001 = -1234567 = 100 = 1234567 >",
+        "group 1000000 =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 1_000_000\n> 1_000_000/1_001\n> -1_234_567\n> 100\n> 1_234_567\n> 1000000\n");
+}
+
+#[test]
+fn continued_fraction_coefficients() {
+    eval(&["22/7 cfrac 355/113 cfrac 3 cfrac"])
+        .assert()
+        .success()
+        .stdout("> [3; 7]\n> [3; 7, 16]\n> [3]\n");
+}
+
+#[test]
+fn time_prints_result_and_duration() {
+    eval(&["2 3 + time"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^> 5\nComputed in [0-9.]+(ns|µs|ms|s)\n$").unwrap());
+}
+
+// Number of steps printed by each profile
+fn profile_steps(lines: &[&str]) -> Vec<u64> {
+    let output = assert_cmd::Command::cargo_bin("rpn-c")
+        .unwrap()
+        .args(lines.iter().flat_map(|line| ["-e", line]))
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("Computed in "))
+        .map(|steps| steps.trim_end_matches(" steps").parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn profile_shows_naive_recursion_blowing_up() {
+    let steps = profile_steps(&[
+        NFIB,
+        "10 nfib profile 20 nfib profile 10 tfib profile 20 tfib profile",
+    ]);
+    let (nfib10, nfib20, tfib10, tfib20) = (steps[0], steps[1], steps[2], steps[3]);
+    // Exponential against linear
+    assert!(nfib20 > 100 * nfib10);
+    assert!(tfib20 < 2 * tfib10 + 10);
+    assert!(nfib10 > 10 * tfib10);
+}
+
+#[test]
+fn modular_inverse_needs_coprimes() {
+    eval(&["3 11 modinv =", "6 9 modinv ="])
+        .assert()
+        .code(1)
+        .stdout("> 4\n")
+        .stderr(predicate::str::contains("6 has no inverse in modulo 9"));
+}
+
+#[test]
+fn tetration_limit_follows_maxbits() {
+    eval(&["2 4 tetr =", "16 maxbits 2 4 tetr ="])
+        .assert()
+        .code(1)
+        .stdout("> 65536\n")
+        .stderr(predicate::str::contains("Result too large, max is 16 bits"));
+}
+
+#[test]
+fn pick_copies_an_expression() {
+    eval(&["1 2 3 + 4 5 * 2 pick : ="])
+        .assert()
+        .success()
+        .stdout("1 2 3 + 4 5 * 2 3 + \n> 5\n");
+}
+
+#[test]
+fn pick_keeps_stack_without_enough_expressions() {
+    eval(&["1 2 3 pick :"])
+        .assert()
+        .code(1)
+        .stdout("1 2 3 \n")
+        .stderr(predicate::str::contains("stack left untouched"));
+}
+
+#[test]
+fn output_goes_to_file_until_closed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("results.txt");
+    eval(&[
+        &format!(
+            "output:{} 2 3 + = 1 2 > 7 # ! 1 4 / [] close",
+            path.display()
+        ),
+        "9 =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 9\n");
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "> 5\n> 2\n> 1\n< 7/1\n> 2.5e-1\n"
+    );
+
+    // Opening the file again truncates it
+    eval(&[&format!("output:{} 1 =", path.display())])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "> 1\n");
+}
+
+#[test]
+fn output_takes_the_reports_too() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("results.txt");
+    eval(&[&format!(
+        "3 =x output:{} 1 2 : -x minmax close",
+        path.display()
+    )])
+    .assert()
+    .success()
+    .stdout("");
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "1 2 \nRemoved x\nNo numbers tracked\n"
+    );
+}
+
+#[test]
+fn output_to_missing_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing").join("results.txt");
+    eval(&[&format!("output:{} 1 =", path.display())])
+        .assert()
+        .success()
+        .stdout("> 1\n")
+        .stderr(predicate::str::contains("Unable to write"));
+}
+
+#[test]
+fn decimals_mode() {
+    eval(&[
+        "3 decimals 1 3 / = 1 2 / = 2 = -1 3 / = 1 3 / 2 3 / >",
+        "fractions 1 3 / =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 0.333…\n> 0.5\n> 2\n> -0.333…\n> 0.666…\n> 0.333…\n> 1/3\n");
+}
+
+#[test]
+fn decimals_are_limited_by_maxbits() {
+    eval(&[
+        "1000000000000 decimals 1 3 / =",
+        "10 maxbits 3 decimals 1 3 / = 4 decimals 1 3 / =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 1/3\n> 0.333…\n> 0.333…\n")
+    .stderr("Digits are too many\nDigits are too many\n");
+}
+
+#[test]
+fn check_reports_incomplete_declarations() {
+    eval(&["$0 $1 + $0 * + check:f|2 :", "check:f@1"])
+        .assert()
+        .success()
+        .stdout(
+            "f|2: found 0 of 1 expressions, the next one misses 1 operands (gave up at index 0)\n\
+             $0 $1 + $0 * + \n\
+             f@1: found 0 of 3 expressions, the next one misses 1 operands (gave up at index 0)\n",
+        );
+}
+
+#[test]
+fn check_declares_nothing() {
+    eval(&["1 + $0 check:f|1 :", "3 f ="])
+        .assert()
+        .code(1)
+        .stdout("f|1: complete, the declaration starts at index 2 of 3\n1 + $0 \n");
+}
+
+#[test]
+fn duplicate_keeps_incomplete_stack() {
+    eval(&["1 2 + * < :", "1 + =x :"])
+        .assert()
+        .code(1)
+        .stdout("1 2 + * \n1 2 + * 1 + \n")
+        .stderr(predicate::str::contains(
+            "Incomplete expression, stack left untouched",
+        ))
+        .stderr(predicate::str::contains("dropped").not());
+}
+
+#[test]
+fn roll_moves_an_expression_to_the_top() {
+    eval(&["1 2 3 4 + 5 6 * 3 roll :", "2 roll : 1 roll :"])
+        .assert()
+        .success()
+        .stdout("1 3 4 + 5 6 * 2 \n1 3 4 + 2 5 6 * \n1 3 4 + 2 5 6 * \n");
+}
+
+#[test]
+fn roll_needs_enough_expressions() {
+    eval(&["1 2 5 roll :"])
+        .assert()
+        .code(1)
+        .stdout("1 2 5 \n")
+        .stderr("Not enough expressions, stack left untouched\n");
+}
+
+#[test]
+fn clamp_is_a_single_expression() {
+    eval(&[
+        "1 5 0 10 clamp ! : 5 0 10 clamp < :",
+        "$0 0 10 clamp c|1 15 c = -2 c =",
+    ])
+    .assert()
+    .success()
+    .stdout("1 \n1 5 5 \n> 10\n> 0\n");
+}
+
+#[test]
+fn negative_number_is_not_a_subtraction() {
+    eval(&["5 -3 : 5 3 - =", "5 -3 + ="])
+        .assert()
+        .success()
+        .stdout("5 -3 \n> 2\n> 2\n")
+        .stderr("");
+}
+
+#[test]
+fn unused_negative_number_warns() {
+    eval(&["5 -3 =", "! 2 -1"])
+        .assert()
+        .success()
+        .stdout("> -3\n")
+        .stderr(
+            "Warning at 2..4: '-3' is a negative number, to subtract write '3 -'\n\
+             Warning at 4..6: '-1' is a negative number, to subtract write '1 -'\n",
+        );
+}
+
+#[test]
+fn trace_logs_each_operation() {
+    eval(&["2 3 + 4 * trace"])
+        .assert()
+        .success()
+        .stdout("2 3 + -> 5\n5 4 * -> 20\n> 20\n");
+}
+
+#[test]
+fn trace_skips_the_other_arm() {
+    eval(&["1 2 3 ? 4 5 / + trace"])
+        .assert()
+        .success()
+        .stdout("3 ? -> 1\n4 5 / -> 4/5\n1 4/5 + -> 9/5\n> 9/5\n");
+}
+
+#[test]
+fn trace_short_circuits() {
+    // x is not defined, computing it would fail
+    eval(&["0 x and trace 1 x or trace"])
+        .assert()
+        .success()
+        .stdout("0 and -> 0\n> 0\n1 or -> 1\n> 1\n");
+}
+
+#[test]
+fn trace_is_a_single_computation_for_maxsteps() {
+    // Each call takes less than 1000 steps, all of them together take more
+    eval(&[
+        "1000 maxsteps $0 1 ~ down 0 $0 ? down|1",
+        "100 down trace",
+        "100 down 100 down + 100 down + trace",
+    ])
+    .assert()
+    .code(1)
+    .stdout(predicate::str::starts_with("100 down -> 0\n> 0\n"))
+    .stderr(predicate::str::contains("Recursion limit exceeded"));
+}
+
+#[test]
+fn keep_variable_leaves_the_value_in_stack() {
+    eval(&["4 6 + :=x : 1 + = x =", "1 2 :=y ! y + ="])
+        .assert()
+        .success()
+        .stdout("10 \n> 11\n> 10\n> 3\n");
+}
+
+#[test]
+fn keep_variable_keeps_incomplete_stack() {
+    eval(&["1 + :=x :", "x ="])
+        .assert()
+        .code(1)
+        .stdout("1 + \n")
+        .stderr(predicate::str::contains(
+            "Incomplete expression, stack left untouched",
+        ));
+}
+
+#[test]
+fn divmod_pushes_quotient_and_remainder() {
+    eval(&["17 5 divmod : = =", "-17 5 divmod = ="])
+        .assert()
+        .success()
+        .stdout("3 2 \n> 2\n> 3\n> 3\n> -4\n");
+}
+
+#[test]
+fn divmod_rejects_zero() {
+    eval(&["17 0 divmod :", "1 + 5 divmod :"])
+        .assert()
+        .code(1)
+        .stdout("\n1 + 5 \n")
+        .stderr(predicate::str::contains("Cannot divide by zero, dropped"))
+        .stderr(predicate::str::contains(
+            "Incomplete expression, stack left untouched",
+        ));
+}
+
+#[test]
+fn approximation_out_of_f64_range() {
+    eval(&["10 400 ^ [] 1 10 400 ^ / [] 3/2 []"])
+        .assert()
+        .success()
+        .stdout("> 1.5e0\n")
+        .stderr(
+            "Value too large for f64, use cfrac or decimals\n\
+             Value too small for f64, use cfrac or decimals\n",
+        );
+}
+
+#[test]
+fn between_is_a_single_expression() {
+    eval(&[
+        "1 5 5 10 between ! :",
+        "$0 0 9 between digit|1 5 digit = 12 digit =",
+    ])
+    .assert()
+    .success()
+    .stdout("1 \n> 1\n> 0\n");
+}
+
+#[test]
+fn flush_tells_which_expression_is_incomplete() {
+    eval(&["1 + 2 3 * 4 + 5 > :"])
+        .assert()
+        .code(1)
+        .stdout("> 5\n> 10\n1 + \n")
+        .stderr("Expression 3 of 3 (from the top) is incomplete\n");
+}
+
+#[test]
+fn sum_and_average_of_the_stack() {
+    eval(&["1 2 3 4 sum : 1 2 3 4 avg :", "avg"])
+        .assert()
+        .success()
+        .stdout("> 10\n\n> 5/2\n\n")
+        .stderr("Average needs at least one value\n");
+}
+
+#[test]
+fn sum_and_average_keep_incomplete_stack() {
+    eval(&["1 + 2 3 sum :", "avg :"])
+        .assert()
+        .code(1)
+        .stdout("1 + 2 3 \n1 + 2 3 \n")
+        .stderr(
+            "Incomplete expression, stack left untouched\n\
+             Incomplete expression, stack left untouched\n",
+        );
+}
+
+#[test]
+fn parts_prints_numerator_and_denominator() {
+    eval(&["6/4 parts -6/4 parts : 3 parts 0 parts :"])
+        .assert()
+        .success()
+        .stdout("> 3 2\n> -3 2\n\n> 3 1\n> 0 1\n\n");
+}
+
+#[test]
+fn fib_operator_matches_nfib() {
+    eval(&[
+        NFIB,
+        // nfib starts from 0 nfib = 0, the operator from 0 fib = 1
+        "99 fib = 1 nfib 0 fib eq 2 nfib 1 fib eq 21 nfib 20 fib eq and and =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 354224848179261915075\n> 1\n");
+}
+
+#[test]
+fn fib_operator_is_faster_than_nfib() {
+    let steps = profile_steps(&[NFIB, "100 fib profile 20 nfib profile"]);
+    // A single step, whatever the index
+    assert_eq!(steps[0], 2);
+    assert!(steps[1] > 100_000);
+}
+
+#[test]
+fn exp_limit_follows_maxbits() {
+    eval(&["2 10 ^ =", "20 maxbits 2 30 ^ ="])
+        .assert()
+        .code(1)
+        .stdout("> 1024\n")
+        .stderr(predicate::str::contains("Result too large, max is 20 bits"));
+}
+
+#[test]
+fn repeat_applies_a_function() {
+    eval(&[
+        "$0 2 * dbl|1 1 10 dbl repeat = 5 0 dbl repeat =",
+        "3 dbl 2 dbl repeat =",
+    ])
+    .assert()
+    .success()
+    .stdout("> 1024\n> 5\n> 24\n");
+}
+
+#[test]
+fn repeat_needs_a_unary_function() {
+    eval(&["$0 $1 + add|2 1 2 add repeat :", "% 3 =v 1 2 v repeat :"])
+        .assert()
+        .code(1)
+        .stdout("1 2 add \n1 2 v \n")
+        .stderr(
+            "add is not a function of arity 1\nStack left untouched\n\
+             v is not a function of arity 1\nStack left untouched\n",
+        );
+}
+
+#[test]
+fn incomplete_expressions_name_the_operator() {
+    eval(&["2 + =", "% 1 2 ? =", "% $0 $1 + add|2 1 add ="])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(
+            "operator '+' needs 2 arguments but found 1\nIncomplete expression\n\
+             operator '?' needs 3 arguments but found 2\nIncomplete expression\n\
+             function 'add' needs 2 arguments but found 1\nIncomplete expression\n",
+        );
+}
+
+#[test]
+fn incomplete_declarations_define_nothing() {
+    eval(&["1 g@1 : + h|2 :", "% 5 g = 5 h ="])
+        .assert()
+        .code(1)
+        .stdout("1 \n1 + \n")
+        .stderr(predicate::str::contains("Incomplete function declaration"));
+}
+
+#[test]
+fn incomplete_declarations_keep_old_definitions() {
+    eval(&["$0 1 + g|1 $0 2 * h|1 1 g@1 : + h|2 :", "% 5 g = 5 h ="])
+        .assert()
+        .code(1)
+        .stdout("1 \n1 + \n> 6\n> 10\n");
+}
+
+#[test]
+fn pushed_results_are_reduced() {
+    eval(&["6/4 # : 12/8 < : 18/12 :=x : x ="])
+        .assert()
+        .success()
+        .stdout("< 3/2\n3/2 \n3/2 3/2 3/2 \n3/2 3/2 3/2 3/2 \n> 3/2\n");
+}
+
+#[test]
+fn decode_prints_a_string_literal() {
+    eval(&["\"hi\" decode \"a\\\"b\\\\c\" decode \"\\n\\t\\01\" decode 0 decode 65 decode"])
+        .assert()
+        .success()
+        .stdout("> \"hi\"\n> \"a\\\"b\\\\c\"\n> \"\\n\\t\\01\"\n> \"\"\n> \"A\"\n");
+}
+
+#[test]
+fn decoded_strings_read_back() {
+    let output = eval(&["\"x\\7fy\\n\" 1000 * decode"]).output().unwrap();
+    let literal = String::from_utf8(output.stdout).unwrap();
+    let literal = literal.trim_start_matches("> ").trim_end();
+    eval(&[&format!("{} \"x\\7fy\\n\" 1000 * eq =", literal)])
+        .assert()
+        .success()
+        .stdout("> 1\n");
+}
+
+#[test]
+fn select_keeps_the_expression_at_the_index() {
+    eval(&[
+        "10 20 30 0 3 select = 10 20 30 1 3 select = 10 20 30 2 3 select =",
+        "10 3 4 * 30 1 1 + 1 - 3 select :",
+    ])
+    .assert()
+    .success()
+    .stdout("> 10\n> 20\n> 30\n3 4 * \n");
+}
+
+#[test]
+fn select_keeps_stack_with_an_index_out_of_range() {
+    eval(&[
+        "10 20 30 3 3 select :",
+        "% 10 20 2 3 select :",
+        "% 10 0 0 select :",
+    ])
+    .assert()
+    .code(1)
+    .stdout("10 20 30 3 3 \n10 20 2 3 \n10 0 0 \n")
+    .stderr(
+        "Index 3 out of range, 0 to 2\nStack left untouched\n\
+         Not enough expressions, stack left untouched\n\
+         Count must be at least 1\nStack left untouched\n",
+    );
+}
+
+#[test]
+fn period_counts_against_maxsteps() {
+    eval(&[
+        "1 7 / period = 1 4 / period =",
+        "1000 maxsteps 1 1000000007 / period =",
+    ])
+    .assert()
+    .code(1)
+    .stdout("> 6\n> 0\n")
+    .stderr("Recursion limit exceeded\n");
+}
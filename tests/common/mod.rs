@@ -0,0 +1,31 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use assert_cmd::Command;
+use std::path::Path;
+
+// The binary without the std_lib, so that only the builtins are tested
+pub fn rpn() -> Command {
+    let mut cmd = Command::cargo_bin("rpn-c").unwrap();
+    cmd.arg("--no-std");
+    cmd
+}
+
+// Evaluates every line with its own -e, in order
+pub fn eval(lines: &[&str]) -> Command {
+    let mut cmd = rpn();
+    for line in lines {
+        cmd.arg("-e").arg(line);
+    }
+    cmd
+}
+
+// The REPL reading input from stdin, with its files kept in home instead of the user's one
+pub fn repl(home: &Path, input: &str) -> Command {
+    let mut cmd = rpn();
+    cmd.args(["--quiet", "--no-history"])
+        .env("HOME", home)
+        .env("XDG_DATA_HOME", home)
+        .write_stdin(input);
+    cmd
+}